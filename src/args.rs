@@ -0,0 +1,190 @@
+use clap::{Parser, Subcommand};
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::network::Heartbeat;
+use crate::timestamp::TimeFormat;
+use crate::{history, message};
+
+/// Args is a struct that contains the command line arguments.
+#[derive(Debug, Parser)]
+#[command(name = "Chatter")]
+pub struct Args {
+    #[arg(short = 's', long = "server")]
+    pub is_server: bool,
+
+    #[arg(short, long, required = false, default_value = "127.0.0.1")]
+    pub address: String,
+
+    #[arg(short, long, required = false, default_value = "23432")]
+    pub port: String,
+
+    #[arg(short = 'u', long, required = false, default_value = "unknown")]
+    pub username: String,
+
+    /// Server: delete messages older than this many days
+    #[arg(long, required = false)]
+    pub retention_days: Option<u32>,
+
+    /// Server: keep at most this many messages per channel
+    #[arg(long, required = false)]
+    pub retention_count: Option<u32>,
+
+    /// Server: append pruned messages to this file before deleting them
+    #[arg(long, required = false)]
+    pub archive: Option<PathBuf>,
+
+    /// Server: seconds between retention runs
+    #[arg(long, required = false, default_value = "60")]
+    #[arg(value_parser = clap::value_parser!(u64).range(1..))]
+    pub prune_interval: u64,
+
+    /// Server: post a notice when users join and leave
+    #[arg(long)]
+    pub announce: bool,
+
+    /// Seconds between pings when the client is idle
+    #[arg(long, required = false, default_value = "10")]
+    #[arg(value_parser = clap::value_parser!(u64).range(1..))]
+    pub heartbeat_interval: u64,
+
+    /// Seconds without a word from the other side before the connection is dropped
+    #[arg(long, required = false, default_value = "30")]
+    #[arg(value_parser = clap::value_parser!(u64).range(1..))]
+    pub heartbeat_timeout: u64,
+
+    /// Server: message of the day, shown in the clients' About window
+    #[arg(long, required = false, default_value = "")]
+    pub motd: String,
+
+    /// Client: how message times are shown
+    #[arg(long, value_enum, required = false, default_value = "24h")]
+    pub time_format: TimeFormat,
+
+    /// Server: the SQLite database file, defaults to the user data directory
+    #[arg(long, required = false)]
+    pub database: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Args {
+    /// Checks what clap can't check one flag at a time.
+    pub fn validate(&self) -> Result<(), String> {
+        // Pinging no more often than the timeout lets a healthy client time out
+        if self.heartbeat_interval >= self.heartbeat_timeout {
+            return Err("--heartbeat-interval must be less than --heartbeat-timeout".to_owned());
+        }
+        Ok(())
+    }
+
+    pub fn heartbeat(&self) -> Heartbeat {
+        Heartbeat {
+            interval: Duration::from_secs(self.heartbeat_interval),
+            timeout: Duration::from_secs(self.heartbeat_timeout),
+        }
+    }
+}
+
+/// Command is a one-off task run against the server's database
+/// instead of starting a server or client.
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Export message history to a file
+    Export {
+        output: PathBuf,
+
+        #[arg(short, long, value_enum, default_value = "jsonl")]
+        format: history::Format,
+
+        /// Only export this channel
+        #[arg(short, long)]
+        channel: Option<String>,
+
+        /// Only export messages on or after this date (YYYY-MM-DD, UTC)
+        #[arg(long)]
+        since: Option<chrono::NaiveDate>,
+
+        /// Only export messages before this date (YYYY-MM-DD, UTC)
+        #[arg(long)]
+        until: Option<chrono::NaiveDate>,
+    },
+
+    /// Import message history from a file
+    Import {
+        input: PathBuf,
+
+        #[arg(short, long, value_enum, default_value = "jsonl")]
+        format: history::Format,
+    },
+
+    /// Write a snapshot of the database, safe while the server is running
+    Backup { output: PathBuf },
+
+    /// Replace the database with a backup. Stop the server first.
+    Restore { input: PathBuf },
+
+    /// Give a user a role, e.g. to appoint the first admin
+    SetRole {
+        username: String,
+
+        #[arg(value_enum)]
+        role: message::Role,
+    },
+
+    /// Connect a bot to the server as --username
+    Bot {
+        #[arg(value_enum)]
+        kind: BotKind,
+
+        /// The channel a notify bot posts to
+        #[arg(short, long, default_value = message::DEFAULT_CHANNEL)]
+        channel: String,
+
+        /// The token printed when the bot first logged in as --username
+        #[arg(long, default_value = "")]
+        token: String,
+    },
+}
+
+/// The bots that come with Chatter.
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum BotKind {
+    /// Replies to "!echo text" with "text"
+    Echo,
+    /// Posts each line of standard input
+    Notify,
+}
+
+//
+// Test Cases
+#[test]
+fn test_intervals_must_be_positive() {
+    assert!(Args::try_parse_from(["chatter", "--heartbeat-timeout", "0"]).is_err());
+    assert!(Args::try_parse_from(["chatter", "--heartbeat-interval", "0"]).is_err());
+    assert!(Args::try_parse_from(["chatter", "--prune-interval", "0"]).is_err());
+    assert_eq!(
+        Args::parse_from(["chatter", "--heartbeat-timeout", "5"])
+            .heartbeat()
+            .timeout,
+        Duration::from_secs(5)
+    );
+}
+
+#[test]
+fn test_heartbeat_interval_below_timeout() {
+    let args = |interval: &str| {
+        Args::parse_from([
+            "chatter",
+            "--heartbeat-interval",
+            interval,
+            "--heartbeat-timeout",
+            "30",
+        ])
+    };
+    assert!(args("10").validate().is_ok());
+    assert!(args("30").validate().is_err());
+    assert!(args("45").validate().is_err());
+}
@@ -0,0 +1,220 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+use turbosql::execute;
+use turbosql::rusqlite::{Connection, OpenFlags};
+
+use crate::database;
+
+/// The migration history this build expects, embedded at compile time.
+const MIGRATIONS_TOML: &str = include_str!("../migrations.toml");
+
+#[derive(Deserialize)]
+struct MigrationsToml {
+    migrations_append_only: Vec<String>,
+}
+
+/// backup() writes a consistent snapshot of the database to `destination`.
+/// It is safe to run while a server is using the same database.
+pub fn backup(destination: &Path) -> Result<(), String> {
+    if destination.exists() {
+        return Err(format!("{} already exists", destination.display()));
+    }
+
+    let destination = destination.to_string_lossy().to_string();
+
+    // VACUUM INTO reads a single transaction, so concurrent writes
+    // either make it into the snapshot completely or not at all.
+    execute!("VACUUM INTO ?", destination).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+/// restore() replaces the database at `database` with the backup at `source`
+/// after checking that the backup is intact and that its migrations are a
+/// prefix of this build's. Missing migrations are applied on the next start.
+/// The replaced database is kept next to it with a `.before-restore-<ms>`
+/// suffix, so every earlier restore keeps its copy too.
+///
+/// The server must be stopped while restoring, it fails if anything else
+/// has the database open.
+pub fn restore(source: &Path, database: &Path) -> Result<PathBuf, String> {
+    validate(source)?;
+    if database.exists() {
+        checkpoint_unused(database)?;
+    }
+
+    let restored_at = database::current_timestamp();
+    let previous = with_suffix(database, &format!(".before-restore-{}", restored_at));
+    if previous.exists() {
+        return Err(format!("{} already exists", previous.display()));
+    }
+    let staged = with_suffix(database, ".restoring");
+
+    // Copy first so a failed copy never leaves us without a database
+    std::fs::copy(source, &staged).map_err(|e| e.to_string())?;
+
+    if database.exists() {
+        std::fs::rename(database, &previous).map_err(|e| e.to_string())?;
+    }
+
+    // Whatever is left of the write-ahead log belongs to the replaced database
+    for suffix in ["-wal", "-shm"] {
+        let log = with_suffix(database, suffix);
+        if log.exists() {
+            std::fs::rename(&log, with_suffix(&previous, suffix)).map_err(|e| e.to_string())?;
+        }
+    }
+
+    std::fs::rename(&staged, database).map_err(|e| e.to_string())?;
+
+    Ok(previous)
+}
+
+// Moves everything in the write-ahead log into the database file, so the
+// copy we keep is complete. Taking an exclusive lock first fails while
+// another connection, e.g. a running server, has the database open.
+fn checkpoint_unused(database: &Path) -> Result<(), String> {
+    let in_use = |e: turbosql::rusqlite::Error| {
+        format!(
+            "{} is in use, stop the server first ({})",
+            database.display(),
+            e
+        )
+    };
+
+    let connection = Connection::open(database).map_err(|e| e.to_string())?;
+    let _ = connection.busy_timeout(Duration::ZERO);
+    connection
+        .execute_batch("PRAGMA locking_mode = EXCLUSIVE; BEGIN EXCLUSIVE; COMMIT;")
+        .map_err(in_use)?;
+    connection
+        .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+        .map_err(in_use)?;
+
+    Ok(())
+}
+
+/// validate() checks a backup file without modifying it.
+pub fn validate(source: &Path) -> Result<(), String> {
+    let connection = Connection::open_with_flags(source, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format!("Could not open {}: {}", source.display(), e))?;
+
+    let integrity: String = connection
+        .query_row("PRAGMA integrity_check", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+
+    if integrity != "ok" {
+        return Err(format!("Backup is corrupt: {}", integrity));
+    }
+
+    let applied = connection
+        .prepare("SELECT migration FROM _turbosql_migrations ORDER BY rowid")
+        .and_then(|mut statement| {
+            statement
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<String>, _>>()
+        })
+        .map_err(|e| format!("Backup has no migration history: {}", e))?;
+
+    check_migrations(&applied, &expected_migrations())
+}
+
+fn expected_migrations() -> Vec<String> {
+    let toml: MigrationsToml = toml::from_str(MIGRATIONS_TOML).expect("Invalid migrations.toml");
+    toml.migrations_append_only
+}
+
+// The backup may be older than this build, but never newer or different.
+fn check_migrations(applied: &[String], expected: &[String]) -> Result<(), String> {
+    // Turbosql ignores commented out migrations
+    let applied: Vec<&String> = applied.iter().filter(|m| !m.starts_with("--")).collect();
+    let expected: Vec<&String> = expected.iter().filter(|m| !m.starts_with("--")).collect();
+
+    if applied.len() > expected.len() {
+        return Err(format!(
+            "Backup has {} migrations but this build only knows {}",
+            applied.len(),
+            expected.len()
+        ));
+    }
+
+    for (i, (a, e)) in applied.iter().zip(&expected).enumerate() {
+        if a != e {
+            return Err(format!("Migration {} differs: {:?} != {:?}", i + 1, a, e));
+        }
+    }
+
+    Ok(())
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+//
+// Test Cases
+#[test]
+fn test_check_migrations() {
+    let expected = expected_migrations();
+
+    assert!(check_migrations(&expected, &expected).is_ok());
+    assert!(check_migrations(&expected[..2], &expected).is_ok());
+
+    let mut newer = expected.clone();
+    newer.push("ALTER TABLE userrow ADD COLUMN unknown TEXT".to_string());
+    assert!(check_migrations(&newer, &expected).is_err());
+
+    let mut different = expected.clone();
+    different[1] = "ALTER TABLE messagerow ADD COLUMN other TEXT".to_string();
+    assert!(check_migrations(&different, &expected).is_err());
+}
+
+#[test]
+fn test_restore_keeps_unsaved_writes() {
+    let dir = std::env::temp_dir().join(format!("rust_chatter_restore_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // A backup without any migrations is older than every build
+    let source = dir.join("backup.sqlite");
+    Connection::open(&source)
+        .unwrap()
+        .execute_batch(
+            "CREATE TABLE _turbosql_migrations (rowid INTEGER PRIMARY KEY, migration TEXT)",
+        )
+        .unwrap();
+
+    let database = dir.join("chat.sqlite");
+    let open = Connection::open(&database).unwrap();
+    open.execute_batch("PRAGMA journal_mode = WAL; PRAGMA wal_autocheckpoint = 0;")
+        .unwrap();
+    open.execute_batch("CREATE TABLE kept (x); INSERT INTO kept VALUES (1);")
+        .unwrap();
+
+    // Not while it is open
+    assert!(restore(&source, &database).unwrap_err().contains("in use"));
+
+    // A server that crashed leaves its writes in the write-ahead log
+    let crashed = dir.join("crashed.sqlite");
+    for suffix in ["", "-wal"] {
+        std::fs::copy(
+            with_suffix(&database, suffix),
+            with_suffix(&crashed, suffix),
+        )
+        .unwrap();
+    }
+    drop(open);
+
+    let previous = restore(&source, &crashed).unwrap();
+    let kept: i64 = Connection::open(&previous)
+        .unwrap()
+        .query_row("SELECT COUNT(*) FROM kept", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(kept, 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
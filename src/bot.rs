@@ -0,0 +1,126 @@
+use std::io::BufRead;
+use std::time::Duration;
+
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc::error::TryRecvError;
+
+use crate::chat_client::ChatClient;
+use crate::message::Message;
+use crate::request::Request;
+use crate::response::Response;
+
+/// Bot reacts to what happens on the server. Bots are run by `BotClient::run`.
+pub trait Bot {
+    /// Called for every new message that was not sent by the bot itself.
+    fn on_message(&mut self, _client: &BotClient, _message: &Message) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Called once per poll, e.g. for reminders. Return false to stop the bot.
+    fn on_tick(&mut self, _client: &BotClient) -> Result<bool, String> {
+        Ok(true)
+    }
+}
+
+/// BotClient runs a `ChatClient` logged in as the bot's user. Bots are
+/// written synchronously, so it keeps its own runtime to drive the client.
+pub struct BotClient {
+    runtime: Runtime,
+    client: ChatClient,
+}
+
+impl BotClient {
+    /// Connects to the server and logs in. `token` is empty on the first
+    /// login, after that it is the one from `token()`.
+    pub fn connect(address: &str, username: &str, token: &str) -> Result<BotClient, String> {
+        let runtime = Runtime::new().map_err(|e| e.to_string())?;
+
+        let client = runtime.block_on(async {
+            let mut client = ChatClient::connect(address).await?;
+            client.set_token(token);
+            client.login(username).await?;
+            Ok::<_, String>(client)
+        })?;
+
+        Ok(BotClient { runtime, client })
+    }
+
+    /// The token to log in as this bot's user next time.
+    pub fn token(&self) -> String {
+        self.client.token()
+    }
+
+    /// Sends a request, turning errors and lost connections into Err.
+    pub fn request(&self, request: Request) -> Result<Response, String> {
+        self.runtime.block_on(self.client.request(request))
+    }
+
+    /// Posts a message to a channel.
+    pub fn send(&self, channel: &str, content: &str) -> Result<(), String> {
+        self.runtime.block_on(self.client.send(channel, content))
+    }
+
+    /// Polls the server every `interval` and hands new messages to the bot,
+    /// until the bot stops or an error occurs.
+    pub fn run(&self, bot: &mut impl Bot, interval: Duration) -> Result<(), String> {
+        let mut messages = self.runtime.block_on(self.client.subscribe(interval))?;
+
+        loop {
+            loop {
+                match messages.try_recv() {
+                    Ok(message) => {
+                        if message.username != self.client.username() {
+                            bot.on_message(self, &message)?;
+                        }
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        return Err("Lost the connection to the server".to_owned())
+                    }
+                }
+            }
+
+            if !bot.on_tick(self)? {
+                return Ok(());
+            }
+
+            std::thread::sleep(interval);
+        }
+    }
+
+    /// Logs the bot out.
+    pub fn disconnect(self) {
+        let _ = self.runtime.block_on(self.client.logout());
+    }
+}
+
+/// Echo replies to "!echo text" with "text", for testing.
+pub struct Echo;
+
+impl Bot for Echo {
+    fn on_message(&mut self, client: &BotClient, message: &Message) -> Result<(), String> {
+        if let Some(text) = message.content.strip_prefix("!echo ") {
+            client.send(&message.channel, text)?;
+        }
+        Ok(())
+    }
+}
+
+/// Notify posts every line it reads to a channel as it arrives,
+/// e.g. piped build output. It stops at the end of the input.
+pub struct Notify<R> {
+    pub channel: String,
+    pub lines: std::io::Lines<R>,
+}
+
+impl<R: BufRead> Bot for Notify<R> {
+    fn on_tick(&mut self, client: &BotClient) -> Result<bool, String> {
+        for line in &mut self.lines {
+            let line = line.map_err(|e| e.to_string())?;
+            if !line.trim().is_empty() {
+                client.send(&self.channel, &line)?;
+            }
+        }
+        Ok(false)
+    }
+}
@@ -0,0 +1,563 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::command::CommandInfo;
+use crate::message::{Channel, Message, Profile, ServerInfo, Unread, User};
+use crate::network::{self, Heartbeat};
+use crate::request::Request;
+use crate::response::Response;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// ChatClient is an async connection to a chat server. Clones share the
+/// connection, so a clone can be handed to another task.
+///
+/// When the connection is lost, requests fail until `reconnect()` succeeds.
+#[derive(Debug, Clone)]
+pub struct ChatClient {
+    address: String,
+    connection: Arc<Mutex<Option<Connection>>>, // None while disconnected
+    connected: Arc<AtomicBool>, // Whether `connection` is Some, readable without the lock
+    username: String,           // Empty until logged in
+    token: Arc<std::sync::Mutex<String>>, // Proves the username is ours, see `set_token()`
+    heartbeat: Heartbeat,
+    stats: Arc<std::sync::Mutex<Stats>>,
+}
+
+// The server can send at any time, e.g. that it is shutting down, so a task
+// reads everything into `responses` for `request()` to pick up. It answers
+// the server's pings itself, so an idle client is not dropped.
+#[derive(Debug)]
+struct Connection {
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    responses: mpsc::UnboundedReceiver<Response>,
+    reader: JoinHandle<()>,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> Connection {
+        let (mut reader, writer) = stream.into_split();
+        let writer = Arc::new(Mutex::new(writer));
+        let (sender, responses) = mpsc::unbounded_channel();
+
+        // The channel closes when the server hangs up
+        let pongs = writer.clone();
+        let reader = tokio::spawn(async move {
+            while let Some(response) = network::get_async(&mut reader).await {
+                if let Response::Ping(n) = response {
+                    let mut writer = pongs.lock().await;
+                    let _ = network::send_async(Request::Pong(n), &mut *writer).await;
+                } else if sender.send(response).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Connection {
+            writer,
+            responses,
+            reader,
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Diagnostics are counters of a client's connection, for the About window.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Diagnostics {
+    pub latency: Option<Duration>, // Of the last ping
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub reconnects: u64, // Connections after the first
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    diagnostics: Diagnostics,
+    connected: bool, // Whether we have connected before
+}
+
+impl ChatClient {
+    /// Connects to a server, e.g. "127.0.0.1:23432". Call login() next.
+    pub async fn connect(address: &str) -> Result<ChatClient, String> {
+        let client = ChatClient::offline(address, "");
+        client.reconnect().await?;
+        Ok(client)
+    }
+
+    /// A client that is not connected yet. `reconnect()` connects
+    /// and logs in as `username`.
+    pub fn offline(address: &str, username: &str) -> ChatClient {
+        ChatClient {
+            address: address.to_owned(),
+            connection: Arc::new(Mutex::new(None)),
+            connected: Arc::default(),
+            username: username.to_owned(),
+            token: Arc::default(),
+            heartbeat: Heartbeat::default(),
+            stats: Arc::default(),
+        }
+    }
+
+    /// Requests that take longer than `heartbeat.timeout` drop the connection.
+    /// The interval is used by `keep_alive()`.
+    pub fn set_heartbeat(&mut self, heartbeat: Heartbeat) {
+        self.heartbeat = heartbeat;
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// The token the server gave us for our username, empty before the
+    /// first login. Keep it to log in as the same user later.
+    pub fn token(&self) -> String {
+        self.token.lock().unwrap().clone()
+    }
+
+    /// Sets the token to log in with, from an earlier `token()`.
+    pub fn set_token(&self, token: &str) {
+        *self.token.lock().unwrap() = token.to_owned();
+    }
+
+    pub fn diagnostics(&self) -> Diagnostics {
+        self.stats.lock().unwrap().diagnostics
+    }
+
+    /// Whether we are connected, as of the last request. It doesn't wait
+    /// for a request that is under way, so the UI can ask every frame.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// Opens a new connection and logs in again, if we were logged in.
+    /// If the server refuses the login we stay connected, but not logged in.
+    pub async fn reconnect(&self) -> Result<(), String> {
+        let connect = TcpStream::connect(&self.address);
+        let connection = match tokio::time::timeout(CONNECT_TIMEOUT, connect).await {
+            Ok(Ok(connection)) => connection,
+            Ok(Err(e)) => return Err(format!("Could not connect to {}: {}", self.address, e)),
+            Err(_) => return Err(format!("Timed out connecting to {}", self.address)),
+        };
+
+        *self.connection.lock().await = Some(Connection::new(connection));
+        self.connected.store(true, Ordering::Relaxed);
+
+        {
+            let mut stats = self.stats.lock().unwrap();
+            if stats.connected {
+                stats.diagnostics.reconnects += 1;
+            }
+            stats.connected = true;
+        }
+
+        if !self.username.is_empty() {
+            self.add_user(&self.username).await?;
+        }
+
+        Ok(())
+    }
+
+    // Logs in with our token, keeping the one the server answers with
+    async fn add_user(&self, username: &str) -> Result<(), String> {
+        let user = User {
+            id: 0,
+            username: username.to_owned(),
+            token: self.token(),
+        };
+        if let Response::Token(token) = self.request(Request::AddUser(user)).await? {
+            self.set_token(&token);
+        }
+        Ok(())
+    }
+
+    /// Drops the connection without logging out.
+    pub async fn disconnect(&self) {
+        *self.connection.lock().await = None;
+        self.connected.store(false, Ordering::Relaxed);
+    }
+
+    /// Sends a request and waits for its response, turning errors
+    /// and lost connections into Err.
+    pub async fn request(&self, request: Request) -> Result<Response, String> {
+        // Hold the lock until the response arrives so it is not read by another task
+        let mut connection = self.connection.lock().await;
+
+        let Some(Connection {
+            writer, responses, ..
+        }) = connection.as_mut()
+        else {
+            return Err("Not connected to the server".to_owned());
+        };
+
+        // A server that stopped answering is as good as gone
+        let sent = network::frame_len(&request);
+        let exchange = async {
+            // What arrived while we were idle comes first, the server may have hung up
+            if let Ok(response) = responses.try_recv() {
+                return Some(response);
+            }
+
+            let mut writer = writer.lock().await;
+            network::send_async(request, &mut *writer).await.ok()?;
+            drop(writer);
+
+            responses.recv().await
+        };
+        let response: Option<Response> = tokio::time::timeout(self.heartbeat.timeout, exchange)
+            .await
+            .unwrap_or(None);
+
+        if let Some(response) = &response {
+            let mut stats = self.stats.lock().unwrap();
+            stats.diagnostics.bytes_sent += sent;
+            stats.diagnostics.bytes_received += network::frame_len(response);
+        }
+
+        match response {
+            Some(Response::Error(e)) => Err(e),
+            Some(Response::ShuttingDown(reason)) => {
+                *connection = None;
+                self.connected.store(false, Ordering::Relaxed);
+                Err(reason)
+            }
+            Some(response) => Ok(response),
+            None => {
+                *connection = None;
+                self.connected.store(false, Ordering::Relaxed);
+                Err("Lost the connection to the server".to_owned())
+            }
+        }
+    }
+
+    /// Pings the server, returning the round trip time.
+    pub async fn ping(&self) -> Result<Duration, String> {
+        let nonce = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64);
+        let start = Instant::now();
+
+        match self.request(Request::Ping(nonce)).await? {
+            Response::Pong(n) if n == nonce => {
+                let latency = start.elapsed();
+                self.stats.lock().unwrap().diagnostics.latency = Some(latency);
+                Ok(latency)
+            }
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Pings the server every heartbeat interval in the background, so a
+    /// dead connection is noticed even when nothing else is sent. Idle
+    /// clients stay connected without it, they answer the server's pings.
+    /// Abort the handle to stop.
+    pub fn keep_alive(&self) -> JoinHandle<()> {
+        let client = self.clone();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(client.heartbeat.interval).await;
+
+                // A failed ping drops the connection, reconnecting is up to the owner
+                if client.is_connected() {
+                    let _ = client.ping().await;
+                }
+            }
+        })
+    }
+
+    /// Logs in, or switches to another username. A username that was used
+    /// before needs its token, set with `set_token()` first.
+    pub async fn login(&mut self, username: &str) -> Result<(), String> {
+        self.add_user(username).await?;
+
+        self.username = username.to_owned();
+        Ok(())
+    }
+
+    pub async fn logout(&self) -> Result<(), String> {
+        let user = User {
+            id: 0,
+            username: self.username.clone(),
+            token: String::new(),
+        };
+        self.request(Request::RemoveUser(user)).await.map(|_| ())
+    }
+
+    /// Posts a message to a channel.
+    pub async fn send(&self, channel: &str, content: &str) -> Result<(), String> {
+        let mut message = Message::new(self.username.clone(), content.to_owned());
+        message.channel = channel.to_owned();
+
+        self.request(Request::AddMessage(message)).await.map(|_| ())
+    }
+
+    /// The messages in a channel, oldest first.
+    pub async fn history(&self, channel: &str) -> Result<Vec<Message>, String> {
+        match self
+            .request(Request::GetChannelMessages(channel.to_owned()))
+            .await?
+        {
+            Response::Messages(messages) => Ok(messages),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// The users that are online.
+    pub async fn users(&self) -> Result<Vec<User>, String> {
+        match self.request(Request::GetUsers()).await? {
+            Response::Users(users) => Ok(users),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// The channels we can read.
+    pub async fn channels(&self) -> Result<Vec<Channel>, String> {
+        match self.request(Request::GetChannels()).await? {
+            Response::Channels(channels) => Ok(channels),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// The slash commands the server understands.
+    pub async fn commands(&self) -> Result<Vec<CommandInfo>, String> {
+        match self.request(Request::GetCommands()).await? {
+            Response::Commands(commands) => Ok(commands),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Runs a server command in a channel, returning its output if it has any.
+    pub async fn run_command(
+        &self,
+        channel: &str,
+        name: &str,
+        args: &str,
+    ) -> Result<Option<String>, String> {
+        let request = Request::RunCommand(channel.to_owned(), name.to_owned(), args.to_owned());
+
+        match self.request(request).await? {
+            Response::Text(text) => Ok(Some(text)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Tells the server we started or stopped typing in a channel.
+    pub async fn typing(&self, channel: &str, started: bool) -> Result<(), String> {
+        self.request(Request::Typing(channel.to_owned(), started))
+            .await
+            .map(|_| ())
+    }
+
+    /// Who else is typing in a channel.
+    pub async fn who_is_typing(&self, channel: &str) -> Result<Vec<String>, String> {
+        match self.request(Request::GetTyping(channel.to_owned())).await? {
+            Response::Typing(users) => Ok(users),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Tells the server we have seen every message up to `id` in a channel.
+    pub async fn mark_read(&self, channel: &str, id: i32) -> Result<(), String> {
+        self.request(Request::MarkRead(channel.to_owned(), id))
+            .await
+            .map(|_| ())
+    }
+
+    /// How far we have read in every channel we can read.
+    pub async fn unread(&self) -> Result<Vec<Unread>, String> {
+        match self.request(Request::GetUnread()).await? {
+            Response::Unread(unread) => Ok(unread),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Who has seen a message.
+    pub async fn readers(&self, channel: &str, id: i32) -> Result<Vec<String>, String> {
+        match self
+            .request(Request::GetReaders(channel.to_owned(), id))
+            .await?
+        {
+            Response::Readers(usernames) => Ok(usernames),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// The newest messages that mention us, newest first.
+    pub async fn mentions(&self, limit: u32) -> Result<Vec<Message>, String> {
+        match self.request(Request::GetMentions(limit)).await? {
+            Response::Messages(messages) => Ok(messages),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// The server's version, uptime and message of the day.
+    pub async fn server_info(&self) -> Result<ServerInfo, String> {
+        match self.request(Request::GetServerInfo()).await? {
+            Response::ServerInfo(info) => Ok(info),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub async fn profile(&self, username: &str) -> Result<Profile, String> {
+        match self
+            .request(Request::GetProfile(username.to_owned()))
+            .await?
+        {
+            Response::Profile(profile) => Ok(profile),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub async fn update_profile(&self, profile: Profile) -> Result<(), String> {
+        self.request(Request::UpdateProfile(profile))
+            .await
+            .map(|_| ())
+    }
+
+    /// Polls the server every `interval` and forwards new messages, in every
+    /// channel we can read, as they arrive. Only messages sent from now on
+    /// are forwarded. The receiver closes when the connection is lost,
+    /// and polling stops when the receiver is dropped.
+    pub async fn subscribe(&self, interval: Duration) -> Result<mpsc::Receiver<Message>, String> {
+        // Start from the newest message, by the server's clock. Only messages
+        // we may read are returned, which can be none, so whatever came
+        // after it is caught up on without being forwarded.
+        let mut last_seen = match self.request(Request::LastMessages(1)).await? {
+            Response::Messages(messages) => messages.first().map_or(0, |m| m.timestamp_ms),
+            _ => 0,
+        };
+        let request = Request::AfterTimestamp(last_seen as u64);
+        if let Response::Messages(missed) = self.request(request).await? {
+            for message in missed {
+                last_seen = last_seen.max(message.timestamp_ms);
+            }
+        }
+
+        let (sender, receiver) = mpsc::channel(100);
+        let client = self.clone();
+
+        tokio::spawn(async move {
+            while !sender.is_closed() {
+                let request = Request::AfterTimestamp(last_seen as u64);
+                let Ok(Response::Messages(messages)) = client.request(request).await else {
+                    return;
+                };
+
+                for message in messages {
+                    last_seen = last_seen.max(message.timestamp_ms);
+
+                    if sender.send(message).await.is_err() {
+                        return;
+                    }
+                }
+
+                tokio::time::sleep(interval).await;
+            }
+        });
+
+        Ok(receiver)
+    }
+}
+
+fn unexpected(response: Response) -> String {
+    format!("Unexpected response from the server: {:?}", response)
+}
+
+/// Backoff is how long to wait between reconnect attempts. The delay
+/// doubles after every failed attempt, up to a limit.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    delay: Duration,
+    min: Duration,
+    max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff::new(Duration::from_secs(1), Duration::from_secs(60))
+    }
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Backoff {
+        Backoff {
+            delay: min,
+            min,
+            max,
+        }
+    }
+
+    /// The delay before the next attempt.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(self.max);
+        delay
+    }
+
+    /// Starts over after a successful attempt.
+    pub fn reset(&mut self) {
+        self.delay = self.min;
+    }
+}
+
+//
+// Test Cases
+#[test]
+fn test_backoff() {
+    let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5));
+
+    let delays: Vec<u64> = (0..5).map(|_| backoff.next_delay().as_secs()).collect();
+    assert_eq!(delays, vec![1, 2, 4, 5, 5]);
+
+    backoff.reset();
+    assert_eq!(backoff.next_delay().as_secs(), 1);
+}
+
+#[test]
+fn test_reconnect() {
+    use crate::server::ChatServer;
+
+    let handle = ChatServer::new().bind("127.0.0.1:0").start().unwrap();
+    let address = handle.address();
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    // Usernames are kept by their token, so use a new one every run
+    let username = format!("reconnect_test_{}", crate::database::current_timestamp());
+    let client = ChatClient::offline(&address.to_string(), &username);
+    runtime.block_on(client.reconnect()).unwrap();
+    assert!(client.is_connected());
+
+    // The server restarts on the same address
+    handle.shutdown();
+    assert!(runtime.block_on(client.users()).is_err());
+    assert!(!client.is_connected());
+
+    let handle = ChatServer::new()
+        .bind(&address.to_string())
+        .start()
+        .unwrap();
+    runtime.block_on(client.reconnect()).unwrap();
+
+    // Only logged in users can post
+    runtime
+        .block_on(client.send("reconnect-test", "back"))
+        .unwrap();
+
+    handle.shutdown();
+}
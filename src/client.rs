@@ -1,44 +1,90 @@
+use chrono::Timelike;
 use eframe::egui;
 use egui::{Align, TextEdit};
-use std::net::TcpStream;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 
-use crate::message::{Message, User};
+use crate::chat_client::{Backoff, ChatClient};
+use crate::command::{self, CommandInfo, Registry};
+use crate::database;
+use crate::filter::Filter;
+use crate::message::{
+    direct_channel, Channel, Message, Profile, Role, ServerInfo, Unread, User, DEFAULT_CHANNEL,
+};
+use crate::network::{Heartbeat, PROTOCOL_VERSION};
+use crate::notification;
+use crate::render;
 use crate::request::Request;
-use crate::response::Response;
-use crate::{network, Args};
+use crate::settings::Settings;
+use crate::theme::Theme;
+use crate::timestamp::{self, Clock, TimeFormat, Zone};
+use crate::typing;
+use crate::Args;
 
 /// client() is the main function for the client.
 pub fn client(args: Arc<Args>) {
-    let address = format!("{}:{}", args.address, args.port);
+    let native_options = eframe::NativeOptions::default();
+    eframe::run_native(
+        "Chatter",
+        native_options,
+        Box::new(move |cc| {
+            let mut settings: Settings = cc
+                .storage
+                .and_then(|storage| eframe::get_value(storage, eframe::APP_KEY))
+                .unwrap_or_default();
+            settings.merge_args(&args);
+            let theme_error = settings.apply(&cc.egui_ctx).err();
+
+            let mut app = App::new(&args, settings);
+            app.theme_error = theme_error;
+            Box::new(app)
+        }),
+    )
+    .unwrap();
+}
+
+pub struct App {
+    runtime: Runtime,
+    heartbeat: Heartbeat,
+    login: Option<Login>, // Shown instead of the chat until we log in, or to add a server
+
+    session: Session,       // The server on screen
+    sessions: Vec<Session>, // Every server we are logged in to, in tab order
+    active: usize,          // The tab on screen, its slot in `sessions` is left empty
 
-    // Connect to the server and get a stream
-    let connection = TcpStream::connect(address).expect("Could not connect to server.");
+    update_interval: f32,
 
-    let user = User {
-        username: args.username.to_owned(),
-        id: 0,
-    };
+    profile: Option<Profile>, // The profile shown in the popup
+    profile_error: Option<String>,
 
-    let app = App {
-        user_list: vec![],
+    commands: Registry<ClientCommand>,
 
-        connection: Arc::new(connection),
-        message_list: vec![],
-        message_box_value: "".to_owned(),
+    readers: Option<(String, Vec<String>)>, // Who has seen the message shown in the popup
+    mentions_open: bool,
 
-        update_interval: 1.0f32,
-        username: args.username.to_owned(),
-    };
+    filter: Filter,
+    filter_open: bool,
+    filter_current: usize, // The match we are at, counted from the oldest
+    filter_scroll: bool,   // Scroll to the current match in the next frame
 
-    let _: Response = network::send_get(Request::AddUser(user), app.connection.clone());
+    about: Option<AboutTab>, // The page of the About window, if it is open
+    server_info: Option<Result<ServerInfo, String>>,
 
-    let native_options = eframe::NativeOptions::default();
-    eframe::run_native("Chatter", native_options, Box::new(|_cc| Box::new(app))).unwrap();
+    clock: Clock, // How message times are shown
+
+    settings: Settings, // Saved between runs
+    settings_open: bool,
+    theme_error: Option<String>, // Why the custom palette could not be used
 }
 
-pub struct App {
-    connection: Arc<TcpStream>,
+/// Session is our connection to one server and what we have loaded from it.
+/// The default session is not connected to anything.
+struct Session {
+    chat: ChatClient,
+    keep_alive: Option<JoinHandle<()>>, // Pings the server while we are logged in
 
     message_box_value: String,
     username: String,
@@ -46,17 +92,1275 @@ pub struct App {
     user_list: Vec<User>,
     message_list: Vec<Message>,
 
-    update_interval: f32,
+    channel_list: Vec<Channel>,
+    channel: String, // The channel we are reading and posting to
+
+    server_commands: Vec<CommandInfo>,
+    command_output: Option<Result<String, String>>, // Shown under the message box
+
+    outbox: Vec<Message>, // Sent once we are connected again
+    backoff: Backoff,
+    retry_at: Instant, // When to try reconnecting
+    reconnecting: Option<JoinHandle<Result<(), String>>>, // Runs while the window keeps drawing
+
+    typing_users: Vec<String>,    // Who else is typing in the channel
+    typing_sent: Option<Instant>, // When we last told the server we are typing
+    typing_edit: Option<Instant>, // When the message box last changed
+
+    unread: Vec<Unread>,
+    divider: Option<i32>, // "New messages" goes after this id, set when the channel is opened
+    marked_read: i32,     // The last id we told the server we have seen
+
+    mentions: Vec<Message>,                    // Newest first
+    incoming: Option<mpsc::Receiver<Message>>, // New messages, for notifications
 }
 
-impl eframe::App for App {
-    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
-        let user = User {
-            username: self.username.to_owned(),
-            id: 0,
+impl Session {
+    fn new(chat: ChatClient, channel: String) -> Session {
+        Session {
+            username: chat.username().to_owned(),
+            chat,
+            keep_alive: None,
+
+            message_box_value: "".to_owned(),
+
+            user_list: vec![],
+            message_list: vec![],
+
+            channel_list: vec![],
+            channel,
+
+            server_commands: vec![],
+            command_output: None,
+
+            outbox: vec![],
+            backoff: Backoff::default(),
+            retry_at: Instant::now(),
+            reconnecting: None,
+
+            typing_users: vec![],
+            typing_sent: None,
+            typing_edit: None,
+
+            unread: vec![],
+            divider: None,
+            marked_read: 0,
+
+            mentions: vec![],
+            incoming: None,
+        }
+    }
+
+    /// Messages we have not read, in every channel.
+    fn unread_count(&self) -> u32 {
+        self.unread.iter().map(|u| u.count).sum()
+    }
+
+    /// Sends queued messages until the outbox is empty or the connection is lost.
+    /// Messages the server refuses are dropped, the last refusal is returned.
+    fn flush_outbox(&mut self, runtime: &Runtime) -> Result<(), String> {
+        let mut result = Ok(());
+
+        while let Some(message) = self.outbox.first() {
+            let sent = runtime.block_on(self.chat.send(&message.channel, &message.content));
+
+            if sent.is_err() && !self.chat.is_connected() {
+                break;
+            }
+
+            self.outbox.remove(0);
+            if let Err(e) = sent {
+                result = Err(e);
+            }
+        }
+
+        result
+    }
+
+    /// Starts reconnecting once we are offline and the backoff delay has
+    /// passed, and returns true on the frame it is done. The outbox is sent and the commands
+    /// loaded again after a reconnect.
+    fn try_reconnect(&mut self, runtime: &Runtime) -> bool {
+        let Some(handle) = &self.reconnecting else {
+            if !self.chat.is_connected() && Instant::now() >= self.retry_at {
+                let chat = self.chat.clone();
+                self.reconnecting = Some(runtime.spawn(async move {
+                    let result = chat.reconnect().await;
+                    if result.is_err() {
+                        // Also drops a connection whose login was refused, e.g. because
+                        // the server has not noticed that our old connection is gone
+                        chat.disconnect().await;
+                    }
+                    result
+                }));
+            }
+            return false;
+        };
+        if !handle.is_finished() {
+            return false;
+        }
+
+        let handle = self.reconnecting.take().unwrap();
+        match runtime.block_on(handle) {
+            Ok(Ok(())) => {
+                self.backoff.reset();
+                self.command_output = self.flush_outbox(runtime).err().map(Err);
+
+                if let Ok(commands) = runtime.block_on(self.chat.commands()) {
+                    self.server_commands = commands;
+                }
+                self.subscribe(runtime);
+                true
+            }
+            _ => {
+                self.retry_at = Instant::now() + self.backoff.next_delay();
+                false
+            }
+        }
+    }
+
+    /// Listens for new messages on the server.
+    fn subscribe(&mut self, runtime: &Runtime) {
+        let incoming = self.chat.subscribe(Duration::from_secs(1));
+        self.incoming = runtime.block_on(incoming).ok();
+    }
+
+    /// Aborts the keep alive and logs out, e.g. when the window closes.
+    fn close(&mut self, runtime: &Runtime) {
+        if let Some(keep_alive) = self.keep_alive.take() {
+            keep_alive.abort();
+        }
+        if let Some(reconnecting) = self.reconnecting.take() {
+            reconnecting.abort();
+        }
+
+        let _ = runtime.block_on(self.chat.logout());
+        runtime.block_on(self.chat.disconnect());
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session::new(ChatClient::offline("", ""), DEFAULT_CHANNEL.to_owned())
+    }
+}
+
+/// AboutTab is a page of the About window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AboutTab {
+    About,
+    Diagnostics, // Of the connection to the server on screen
+}
+
+/// Login is what has been entered on the login screen.
+struct Login {
+    server: String,
+    username: String,
+    error: Option<String>, // Why the last attempt failed
+    connecting: Option<JoinHandle<Result<ChatClient, String>>>, // The attempt in progress
+}
+
+impl Login {
+    fn new(settings: &Settings) -> Login {
+        Login {
+            server: settings.server().unwrap_or_default().to_owned(),
+            username: settings.username.clone(),
+            error: None,
+            connecting: None,
+        }
+    }
+}
+
+/// ClientCommand handles a slash command without asking the server.
+/// It returns text to show the user, or an error.
+type ClientCommand = fn(&mut App, &str) -> Result<Option<String>, String>;
+
+impl App {
+    /// Creates the app with the saved settings. It starts on the login screen.
+    fn new(args: &Args, settings: Settings) -> App {
+        // The GUI is synchronous, so it drives the client on its own runtime
+        let runtime = Runtime::new().expect("Could not start the runtime.");
+
+        App {
+            runtime,
+            heartbeat: args.heartbeat(),
+            login: Some(Login::new(&settings)),
+
+            session: Session::default(),
+            sessions: vec![],
+            active: 0,
+
+            update_interval: 1.0f32,
+
+            profile: None,
+            profile_error: None,
+
+            commands: App::commands(),
+
+            readers: None,
+            mentions_open: false,
+
+            filter: Filter::default(),
+            filter_open: false,
+            filter_current: 0,
+            filter_scroll: false,
+
+            about: None,
+            server_info: None,
+
+            clock: Clock {
+                format: settings.time_format,
+                zone: Zone::Local,
+            },
+
+            settings,
+            settings_open: false,
+            theme_error: None,
+        }
+    }
+
+    /// Connects and logs in with what was entered on the login screen.
+    /// Errors are shown on the login screen.
+    fn connect(&mut self) {
+        let Some(login) = &mut self.login else {
+            return;
+        };
+
+        let server = login.server.trim().to_owned();
+        let username = login.username.trim().to_owned();
+
+        if !server.contains(':') {
+            login.error = Some("Servers are written as address:port".to_owned());
+            return;
+        }
+        if username.is_empty() {
+            login.error = Some("Enter a username".to_owned());
+            return;
+        }
+
+        let mut chat = ChatClient::offline(&server, &username);
+        chat.set_heartbeat(self.heartbeat);
+        chat.set_token(self.settings.token(&server, &username));
+
+        // Connecting can take a while, the window keeps drawing meanwhile
+        login.error = None;
+        login.connecting = Some(self.runtime.spawn(async move {
+            match chat.reconnect().await {
+                Ok(()) => Ok(chat),
+                Err(e) => {
+                    // The server may be up but have refused the username
+                    chat.disconnect().await;
+                    Err(e)
+                }
+            }
+        }));
+    }
+
+    /// Picks up the connection started by `connect()` once it is done.
+    fn poll_connect(&mut self) {
+        let Some(login) = &mut self.login else {
+            return;
+        };
+        match &login.connecting {
+            Some(handle) if handle.is_finished() => {}
+            _ => return,
+        }
+
+        let handle = login.connecting.take().unwrap();
+        let chat = match self.runtime.block_on(handle) {
+            Ok(Ok(chat)) => chat,
+            Ok(Err(e)) => {
+                login.error = Some(e);
+                return;
+            }
+            Err(e) => {
+                login.error = Some(e.to_string());
+                return;
+            }
+        };
+
+        self.settings.add_server(chat.address().to_owned());
+        self.settings.username = chat.username().to_owned();
+        self.settings
+            .set_token(chat.address(), chat.username(), chat.token());
+        self.login = None;
+
+        let session = Session::new(chat, self.settings.last_channel.clone());
+        self.add_session(session);
+
+        // Times are shown in the timezone from our profile
+        self.update_zone();
+
+        // The server tells us which commands it understands
+        if let Ok(commands) = self.runtime.block_on(self.session.chat.commands()) {
+            self.session.server_commands = commands;
+        }
+
+        self.session.subscribe(&self.runtime);
+
+        // Keep the connection alive while the window is idle
+        let _runtime = self.runtime.enter();
+        self.session.keep_alive = Some(self.session.chat.keep_alive());
+    }
+
+    /// Logs out of the server on screen and closes its tab.
+    /// Without another server we go back to the login screen.
+    fn logout(&mut self) {
+        self.session.close(&self.runtime);
+
+        self.settings.last_channel = self.session.channel.clone();
+        self.sessions.remove(self.active);
+
+        if self.sessions.is_empty() {
+            self.session = Session::default();
+            self.login = Some(Login::new(&self.settings));
+            return;
+        }
+
+        self.active = self.active.min(self.sessions.len() - 1);
+        self.session = std::mem::take(&mut self.sessions[self.active]);
+        self.after_switch();
+    }
+
+    /// Puts a new session on screen, in a tab of its own.
+    fn add_session(&mut self, session: Session) {
+        if !self.sessions.is_empty() {
+            std::mem::swap(&mut self.session, &mut self.sessions[self.active]);
+        }
+
+        self.sessions.push(Session::default());
+        self.active = self.sessions.len() - 1;
+        self.session = session;
+        self.update_interval = 0.0; // Load the server right away
+    }
+
+    /// Puts the server in another tab on screen.
+    fn switch_server(&mut self, index: usize) {
+        // We are no longer typing on the server we leave
+        if self.session.typing_sent.take().is_some() {
+            let _ = self
+                .runtime
+                .block_on(self.session.chat.typing(&self.session.channel, false));
+        }
+
+        std::mem::swap(&mut self.session, &mut self.sessions[self.active]);
+        self.active = index;
+        std::mem::swap(&mut self.session, &mut self.sessions[self.active]);
+        self.after_switch();
+    }
+
+    /// The search bar over the loaded messages, with next and previous buttons
+    /// to step through the matches.
+    fn filter_bar(&mut self, ui: &mut egui::Ui) {
+        let username = &self.session.username;
+        let count = self
+            .session
+            .message_list
+            .iter()
+            .filter(|m| self.filter.matches(m, username, &self.clock))
+            .count();
+        let current = self.filter_current.min(count.saturating_sub(1));
+
+        let before = self.filter.clone();
+        let mut step = None;
+
+        ui.horizontal(|ui| {
+            let text = ui.add(
+                TextEdit::singleline(&mut self.filter.text)
+                    .hint_text("Search")
+                    .desired_width(160.0),
+            );
+            if text.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                step = Some(current + 1);
+                text.request_focus();
+            }
+
+            ui.add(
+                TextEdit::singleline(&mut self.filter.author)
+                    .hint_text("Author")
+                    .desired_width(80.0),
+            );
+            ui.add(
+                TextEdit::singleline(&mut self.filter.date)
+                    .hint_text("2023-04-01")
+                    .desired_width(80.0),
+            );
+            ui.checkbox(&mut self.filter.only_mentions, "Only mentions");
+
+            if ui.small_button("⬆").on_hover_text("Previous").clicked() {
+                step = Some(current + count.max(1) - 1);
+            }
+            if ui.small_button("⬇").on_hover_text("Next").clicked() {
+                step = Some(current + 1);
+            }
+
+            match count {
+                0 => ui.weak("No matches"),
+                n => ui.weak(format!("{} of {}", current + 1, n)),
+            };
+
+            if let Err(e) = self.filter.date() {
+                ui.colored_label(ui.visuals().error_fg_color, e);
+            }
+
+            if ui.small_button("✖").on_hover_text("Close").clicked() {
+                self.filter_open = false;
+            }
+        });
+
+        // A new search starts at the newest match
+        if self.filter != before {
+            self.filter_current = usize::MAX;
+            self.filter_scroll = true;
+        } else if let Some(index) = step {
+            self.filter_current = index % count.max(1);
+            self.filter_scroll = true;
+        }
+    }
+
+    // Popups belong to the server we left
+    fn after_switch(&mut self) {
+        self.profile = None;
+        self.readers = None;
+        self.update_zone();
+        self.update_interval = 0.0;
+    }
+
+    /// The tabs of the servers we are logged in to, with their unread counts.
+    fn server_tabs(&mut self, ui: &mut egui::Ui) {
+        let mut switch = None;
+
+        ui.horizontal(|ui| {
+            for index in 0..self.sessions.len() {
+                let session = match index == self.active {
+                    true => &self.session,
+                    false => &self.sessions[index],
+                };
+
+                // Unread counts are stale while we are disconnected
+                let mut label = session.chat.address().to_owned();
+                let unread = session.unread_count();
+                if !session.chat.is_connected() {
+                    label = format!("{} (offline)", label);
+                } else if unread > 0 {
+                    label = format!("{} ({})", label, unread);
+                }
+
+                let tab = ui.selectable_label(index == self.active, label);
+                if tab.on_hover_text(&session.username).clicked() && index != self.active {
+                    switch = Some(index);
+                }
+            }
+
+            if ui
+                .button("+")
+                .on_hover_text("Connect to another server")
+                .clicked()
+            {
+                self.login = Some(Login::new(&self.settings));
+            }
+        });
+
+        if let Some(index) = switch {
+            self.switch_server(index);
+        }
+    }
+
+    /// The startup screen, where a server and username are picked.
+    fn login_screen(&mut self, ctx: &egui::Context) {
+        let Some(login) = &mut self.login else {
+            return;
+        };
+        let mut connect = false;
+        let mut cancel = false;
+        let connecting = login.connecting.is_some();
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.add_space(ui.available_height() / 4.0);
+                ui.heading("Chatter");
+                ui.add_space(8.0);
+
+                egui::Grid::new("login_grid").num_columns(2).show(ui, |ui| {
+                    ui.label("Server");
+                    ui.horizontal(|ui| {
+                        ui.text_edit_singleline(&mut login.server)
+                            .on_hover_text("address:port");
+
+                        // Servers we have used before
+                        egui::ComboBox::from_id_source("recent_servers")
+                            .selected_text("Recent")
+                            .show_ui(ui, |ui| {
+                                for server in &self.settings.servers {
+                                    if ui.selectable_label(false, server).clicked() {
+                                        login.server = server.clone();
+                                    }
+                                }
+                            });
+                    });
+                    ui.end_row();
+
+                    ui.label("Username");
+                    let username = ui.text_edit_singleline(&mut login.username);
+                    if username.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                        connect = true;
+                    }
+                    ui.end_row();
+                });
+
+                ui.add_space(8.0);
+                ui.horizontal(|ui| {
+                    let button = egui::Button::new("Connect");
+                    if ui.add_enabled(!connecting, button).clicked() {
+                        connect = true;
+                    }
+                    // Back to the servers we are already logged in to
+                    if !self.sessions.is_empty() && ui.button("Cancel").clicked() {
+                        cancel = true;
+                    }
+                });
+
+                if connecting {
+                    ctx.request_repaint_after(Duration::from_millis(100));
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label(format!("Connecting to {}…", login.server.trim()));
+                    });
+                }
+
+                if let Some(error) = &login.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+            });
+        });
+
+        if connect && !connecting {
+            self.connect();
+        } else if cancel {
+            // An attempt still running is dropped along with the login screen
+            if let Some(handle) = login.connecting.take() {
+                handle.abort();
+            }
+            self.login = None;
+        }
+    }
+
+    /// The commands the client handles itself.
+    fn commands() -> Registry<ClientCommand> {
+        let mut commands: Registry<ClientCommand> = Registry::default();
+
+        commands.register(
+            CommandInfo::new("help", "", "List the available commands"),
+            App::help_command,
+        );
+        commands.register(
+            CommandInfo::new("join", "<channel>", "Switch to a channel"),
+            App::join_command,
+        );
+        commands.register(
+            CommandInfo::new("me", "<action>", "Describe what you are doing"),
+            App::me_command,
+        );
+        commands.register(
+            CommandInfo::new("msg", "<user> <message>", "Send a direct message"),
+            App::msg_command,
+        );
+        commands.register(
+            CommandInfo::new("nick", "<name>", "Change your display name"),
+            App::nick_command,
+        );
+        commands.register(
+            CommandInfo::new("time", "<24h|12h|relative>", "Change how times are shown"),
+            App::time_command,
+        );
+
+        commands
+    }
+
+    /// Client commands followed by the server's
+    fn all_commands(&self) -> Vec<CommandInfo> {
+        let mut all = self.commands.infos();
+        all.extend(self.session.server_commands.iter().cloned());
+        all
+    }
+
+    /// Sends the message box, either as a message or as a command.
+    fn submit(&mut self) {
+        let input = std::mem::take(&mut self.session.message_box_value);
+
+        if input.trim().is_empty() {
+            return;
+        }
+
+        self.session.command_output = match command::parse(&input) {
+            Some((name, args)) => self.run_command(name, args),
+            // "//text" sends "/text"
+            None => match input.strip_prefix("//") {
+                Some(text) => self.send_message(self.session.channel.clone(), format!("/{}", text)),
+                None => self.send_message(self.session.channel.clone(), input),
+            }
+            .err()
+            .map(Err),
+        };
+    }
+
+    fn run_command(&mut self, name: &str, args: &str) -> Option<Result<String, String>> {
+        if let Some(handler) = self.commands.get(name) {
+            return handler(self, args).transpose();
+        }
+
+        if !self
+            .session
+            .server_commands
+            .iter()
+            .any(|info| info.name == name)
+        {
+            return Some(Err(format!("Unknown command /{}, try /help", name)));
+        }
+
+        self.runtime
+            .block_on(
+                self.session
+                    .chat
+                    .run_command(&self.session.channel, name, args),
+            )
+            .transpose()
+    }
+
+    /// Sends a message, or queues it while we are offline.
+    fn send_message(&mut self, channel: String, content: String) -> Result<(), String> {
+        let mut message = Message::new(self.session.username.clone(), content);
+        message.channel = channel;
+
+        // The server sets the time, until then it shows when it was queued
+        message.timestamp_ms = database::current_timestamp();
+
+        // Queued messages go first to keep the order
+        self.session.outbox.push(message);
+        self.session.flush_outbox(&self.runtime)
+    }
+
+    /// Shows a notification for each new message we want to hear about,
+    /// on every server, while the window is in the background.
+    fn notify(&mut self, ctx: &egui::Context) {
+        let focused = ctx.input(|i| i.raw.has_focus);
+        let hour = chrono::Local::now().hour();
+        let settings = &self.settings.notifications;
+
+        for session in std::iter::once(&mut self.session).chain(&mut self.sessions) {
+            let Some(incoming) = &mut session.incoming else {
+                continue;
+            };
+
+            while let Ok(message) = incoming.try_recv() {
+                let server = session.chat.address();
+                if focused || !settings.wanted(server, &message, &session.username, hour) {
+                    continue;
+                }
+
+                let summary = match message.mentions(&session.username) {
+                    true => format!("{} mentioned you in #{}", message.username, message.channel),
+                    false => format!("{} in #{}", message.username, message.channel),
+                };
+                notification::show(summary, message.content, settings.sound);
+            }
+        }
+    }
+
+    fn help_command(&mut self, _args: &str) -> Result<Option<String>, String> {
+        let lines: Vec<String> = self
+            .all_commands()
+            .iter()
+            .map(|info| format!("/{} {} - {}", info.name, info.usage, info.help))
+            .collect();
+
+        Ok(Some(lines.join("\n")))
+    }
+
+    fn join_command(&mut self, args: &str) -> Result<Option<String>, String> {
+        let channel = args.trim().trim_start_matches('#');
+
+        if channel.is_empty() || channel.contains(char::is_whitespace) || channel.starts_with('@') {
+            return Err("Usage: /join <channel>".to_owned());
+        }
+
+        self.switch_channel(channel.to_owned());
+        Ok(None)
+    }
+
+    fn me_command(&mut self, args: &str) -> Result<Option<String>, String> {
+        if args.is_empty() {
+            return Err("Usage: /me <action>".to_owned());
+        }
+
+        self.send_message(self.session.channel.clone(), format!("/me {}", args))?;
+        Ok(None)
+    }
+
+    fn msg_command(&mut self, args: &str) -> Result<Option<String>, String> {
+        let (username, text) = command::split_first(args);
+
+        if username.is_empty() || text.is_empty() {
+            return Err("Usage: /msg <user> <message>".to_owned());
+        }
+
+        let channel = direct_channel(&self.session.username, username);
+        self.send_message(channel.clone(), text.to_owned())?;
+        self.switch_channel(channel);
+        Ok(None)
+    }
+
+    fn nick_command(&mut self, args: &str) -> Result<Option<String>, String> {
+        let Ok(mut profile) = self
+            .runtime
+            .block_on(self.session.chat.profile(&self.session.username))
+        else {
+            return Err("Could not load your profile".to_owned());
+        };
+
+        profile.display_name = args.to_owned();
+
+        self.runtime
+            .block_on(self.session.chat.update_profile(profile))?;
+        Ok(Some(format!("You are now known as {}", args)))
+    }
+
+    fn time_command(&mut self, args: &str) -> Result<Option<String>, String> {
+        use clap::ValueEnum;
+
+        self.clock.format = TimeFormat::from_str(args.trim(), true)
+            .map_err(|_| "Usage: /time <24h|12h|relative>".to_owned())?;
+        self.settings.time_format = self.clock.format;
+        Ok(None)
+    }
+
+    /// Uses the timezone from our profile, or local time if it is not set.
+    fn update_zone(&mut self) {
+        if let Ok(profile) = self
+            .runtime
+            .block_on(self.session.chat.profile(&self.session.username))
+        {
+            self.clock.zone = Zone::parse(&profile.timezone).unwrap_or_default();
+        }
+    }
+
+    /// Tells the server whether we are typing: the message box has focus
+    /// and was edited lately. While the edits go on it is repeated every
+    /// few seconds so it does not expire.
+    fn update_typing(&mut self, changed: bool, focused: bool) {
+        if changed {
+            self.session.typing_edit = Some(Instant::now());
+        }
+        let edited = self.session.typing_edit;
+        let typing = focused
+            && !self.session.message_box_value.trim().is_empty()
+            && edited.is_some_and(|edited| edited.elapsed() < typing::IDLE);
+
+        match (typing, self.session.typing_sent) {
+            (true, Some(sent))
+                if sent.elapsed() < typing::REPEAT || edited.is_some_and(|e| e <= sent) => {}
+            (true, _) => {
+                let _ = self
+                    .runtime
+                    .block_on(self.session.chat.typing(&self.session.channel, true));
+                self.session.typing_sent = Some(Instant::now());
+            }
+            (false, Some(_)) => {
+                let _ = self
+                    .runtime
+                    .block_on(self.session.chat.typing(&self.session.channel, false));
+                self.session.typing_sent = None;
+            }
+            (false, None) => {}
+        }
+    }
+
+    fn switch_channel(&mut self, channel: String) {
+        // We are no longer typing in the old channel
+        if self.session.typing_sent.take().is_some() {
+            let _ = self
+                .runtime
+                .block_on(self.session.chat.typing(&self.session.channel, false));
+        }
+
+        self.session.channel = channel;
+        self.session.message_list.clear();
+        self.session.typing_users.clear();
+        self.session.divider = None;
+        self.session.marked_read = 0;
+        self.update_interval = 0.0; // Load the channel right away
+    }
+
+    /// Fetches a profile from the server and shows it in the popup.
+    fn open_profile(&mut self, username: &str) {
+        if let Ok(profile) = self.runtime.block_on(self.session.chat.profile(username)) {
+            self.profile = Some(profile);
+            self.profile_error = None;
+        }
+    }
+
+    /// Fetches who has seen a message and shows them in a popup.
+    fn open_readers(&mut self, id: i32) {
+        let Some(message) = self.session.message_list.iter().find(|m| m.id == id) else {
+            return;
+        };
+        let title = message.to_string();
+
+        match self
+            .runtime
+            .block_on(self.session.chat.readers(&self.session.channel, id))
+        {
+            Ok(usernames) => self.readers = Some((title, usernames)),
+            Err(e) => self.session.command_output = Some(Err(e)),
+        }
+    }
+
+    fn readers_window(&mut self, ctx: &egui::Context) {
+        let Some((title, usernames)) = &self.readers else {
+            return;
+        };
+
+        let mut open = true;
+        egui::Window::new("Seen by")
+            .id(egui::Id::new("readers_window"))
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.weak(title);
+                ui.separator();
+
+                if usernames.is_empty() {
+                    ui.label("Nobody yet");
+                }
+                for username in usernames {
+                    ui.label(username);
+                }
+            });
+
+        if !open {
+            self.readers = None;
+        }
+    }
+
+    /// Lists the messages that mention us. Clicking one opens its channel.
+    fn mentions_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.mentions_open;
+        let mut switch = None;
+
+        egui::Window::new("Mentions")
+            .id(egui::Id::new("mentions_window"))
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                if self.session.mentions.is_empty() {
+                    ui.label("Nobody has mentioned you yet");
+                }
+
+                egui::scroll_area::ScrollArea::vertical().show(ui, |ui| {
+                    for message in &self.session.mentions {
+                        let text = format!("#{} {}", message.channel, message);
+                        if ui.selectable_label(false, text).clicked() {
+                            switch = Some(message.channel.clone());
+                        }
+                    }
+                });
+            });
+
+        self.mentions_open = open;
+
+        if let Some(channel) = switch {
+            self.mentions_open = false;
+            if channel != self.session.channel {
+                self.switch_channel(channel);
+            }
+        }
+    }
+
+    /// Shows the settings. Changes are used right away, except
+    /// the username and server which are used when we next connect.
+    fn settings_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.settings_open;
+        let before = self.settings.clone();
+        let settings = &mut self.settings;
+        let theme_error = &self.theme_error;
+        let mut reload = false;
+
+        egui::Window::new("Settings")
+            .id(egui::Id::new("settings_window"))
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("settings_grid")
+                    .num_columns(2)
+                    .show(ui, |ui| {
+                        ui.label("Username");
+                        ui.text_edit_singleline(&mut settings.username);
+                        ui.end_row();
+
+                        ui.label("Theme");
+                        ui.horizontal(|ui| {
+                            let themes = [
+                                (Theme::Dark, "Dark"),
+                                (Theme::Light, "Light"),
+                                (Theme::HighContrast, "High contrast"),
+                                (Theme::Custom, "Custom"),
+                            ];
+                            for (theme, name) in themes {
+                                ui.selectable_value(&mut settings.theme, theme, name);
+                            }
+                        });
+                        ui.end_row();
+
+                        if settings.theme == Theme::Custom {
+                            ui.label("Palette file");
+                            ui.horizontal(|ui| {
+                                ui.text_edit_singleline(&mut settings.palette)
+                                    .on_hover_text(
+                                        "A TOML file with colors like background = \"#1e1e2e\"",
+                                    );
+                                reload = ui.button("Load").clicked();
+                            });
+                            ui.end_row();
+                        }
+
+                        ui.label("Font size");
+                        ui.add(egui::Slider::new(&mut settings.font_size, 10.0..=24.0));
+                        ui.end_row();
+
+                        ui.label("Times");
+                        ui.horizontal(|ui| {
+                            let formats = [
+                                (TimeFormat::H24, "24h"),
+                                (TimeFormat::H12, "12h"),
+                                (TimeFormat::Relative, "Relative"),
+                            ];
+                            for (format, name) in formats {
+                                ui.selectable_value(&mut settings.time_format, format, name);
+                            }
+                        });
+                        ui.end_row();
+
+                        let notifications = &mut settings.notifications;
+                        ui.label("Notifications");
+                        ui.vertical(|ui| {
+                            ui.checkbox(&mut notifications.messages, "For new messages");
+                            ui.checkbox(&mut notifications.mentions, "When I am mentioned");
+                            ui.checkbox(&mut notifications.sound, "Play a sound");
+                        });
+                        ui.end_row();
+
+                        ui.label("Do not disturb");
+                        ui.horizontal(|ui| {
+                            ui.checkbox(&mut notifications.do_not_disturb, "From");
+                            ui.add(
+                                egui::DragValue::new(&mut notifications.quiet_start)
+                                    .clamp_range(0..=23),
+                            );
+                            ui.label("until");
+                            ui.add(
+                                egui::DragValue::new(&mut notifications.quiet_end)
+                                    .clamp_range(0..=23),
+                            );
+                        });
+                        ui.end_row();
+                    });
+
+                if let Some(error) = theme_error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+
+                ui.separator();
+                ui.label("Servers");
+
+                let mut forget = None;
+                for (index, server) in settings.servers.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        if ui.small_button("✖").on_hover_text("Forget").clicked() {
+                            forget = Some(index);
+                        }
+                        ui.label(server);
+                    });
+                }
+                if let Some(index) = forget {
+                    settings.servers.remove(index);
+                }
+
+                ui.weak("The username and server are used when you next connect");
+            });
+
+        self.settings_open = open;
+
+        if reload
+            || self.settings.theme != before.theme
+            || self.settings.font_size != before.font_size
+        {
+            self.theme_error = self.settings.apply(ctx).err();
+        }
+        self.clock.format = self.settings.time_format;
+    }
+
+    /// Opens the About window, asking the server about itself.
+    fn open_about(&mut self) {
+        self.about = Some(AboutTab::About);
+        self.server_info = Some(self.runtime.block_on(self.session.chat.server_info()));
+    }
+
+    /// Shows our version and the server's, and how the connection is doing.
+    fn about_window(&mut self, ctx: &egui::Context) {
+        let Some(tab) = &mut self.about else {
+            return;
         };
 
-        let _: Response = network::send_get(Request::RemoveUser(user), self.connection.clone());
+        let mut open = true;
+        let mut refresh = false;
+
+        egui::Window::new("About")
+            .id(egui::Id::new("about_window"))
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.selectable_value(tab, AboutTab::About, "About");
+                    ui.selectable_value(tab, AboutTab::Diagnostics, "Diagnostics");
+                });
+                ui.separator();
+
+                match tab {
+                    AboutTab::About => {
+                        ui.heading("Chatter");
+                        egui::Grid::new("about_grid").num_columns(2).show(ui, |ui| {
+                            ui.label("Version");
+                            ui.label(env!("CARGO_PKG_VERSION"));
+                            ui.end_row();
+
+                            ui.label("Protocol");
+                            ui.label(PROTOCOL_VERSION.to_string());
+                            ui.end_row();
+                        });
+
+                        ui.separator();
+                        ui.strong(format!("Server {}", self.session.chat.address()));
+
+                        match &self.server_info {
+                            Some(Ok(info)) => {
+                                egui::Grid::new("server_grid")
+                                    .num_columns(2)
+                                    .show(ui, |ui| {
+                                        ui.label("Version");
+                                        ui.label(&info.version);
+                                        ui.end_row();
+
+                                        ui.label("Protocol");
+                                        match info.protocol == PROTOCOL_VERSION {
+                                            true => ui.label(info.protocol.to_string()),
+                                            false => ui.colored_label(
+                                                ui.visuals().warn_fg_color,
+                                                format!(
+                                                    "{}, we speak {}",
+                                                    info.protocol, PROTOCOL_VERSION
+                                                ),
+                                            ),
+                                        };
+                                        ui.end_row();
+
+                                        ui.label("Uptime");
+                                        ui.label(timestamp::describe_duration(info.uptime_secs));
+                                        ui.end_row();
+                                    });
+
+                                if !info.motd.is_empty() {
+                                    ui.separator();
+                                    ui.label(&info.motd);
+                                }
+                            }
+                            Some(Err(e)) => {
+                                ui.colored_label(ui.visuals().error_fg_color, e);
+                            }
+                            None => {}
+                        }
+
+                        refresh = ui.button("Refresh").clicked();
+                    }
+                    AboutTab::Diagnostics => {
+                        let diagnostics = self.session.chat.diagnostics();
+                        let connected = self.session.chat.is_connected();
+
+                        egui::Grid::new("diagnostics_grid")
+                            .num_columns(2)
+                            .show(ui, |ui| {
+                                ui.label("Server");
+                                ui.label(self.session.chat.address());
+                                ui.end_row();
+
+                                ui.label("Connected");
+                                ui.label(if connected { "Yes" } else { "No" });
+                                ui.end_row();
+
+                                ui.label("Latency");
+                                ui.label(match diagnostics.latency {
+                                    Some(latency) => format!("{} ms", latency.as_millis()),
+                                    None => "Not measured yet".to_owned(),
+                                });
+                                ui.end_row();
+
+                                ui.label("Sent");
+                                ui.label(describe_bytes(diagnostics.bytes_sent));
+                                ui.end_row();
+
+                                ui.label("Received");
+                                ui.label(describe_bytes(diagnostics.bytes_received));
+                                ui.end_row();
+
+                                ui.label("Reconnects");
+                                ui.label(diagnostics.reconnects.to_string());
+                                ui.end_row();
+                            });
+                    }
+                }
+            });
+
+        if refresh {
+            self.server_info = Some(self.runtime.block_on(self.session.chat.server_info()));
+        }
+        if !open {
+            self.about = None;
+        }
+    }
+
+    /// Shows the profile popup. Our own profile can be edited.
+    fn profile_window(&mut self, ctx: &egui::Context) {
+        let Some(profile) = &mut self.profile else {
+            return;
+        };
+
+        let editable = profile.username == self.session.username;
+        let mut open = true;
+        let mut save = false;
+        let mut moderate = None;
+
+        egui::Window::new(profile.name().to_owned())
+            .id(egui::Id::new("profile_window"))
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("profile_grid")
+                    .num_columns(2)
+                    .show(ui, |ui| {
+                        ui.label("Username");
+                        ui.label(&profile.username);
+                        ui.end_row();
+
+                        ui.label("Role");
+                        ui.label(profile.role.to_string());
+                        ui.end_row();
+
+                        let fields = [
+                            ("Display name", &mut profile.display_name),
+                            ("Status", &mut profile.status),
+                            ("Avatar", &mut profile.avatar),
+                            ("Timezone", &mut profile.timezone),
+                        ];
+
+                        for (name, value) in fields {
+                            ui.label(name);
+                            if editable {
+                                ui.text_edit_singleline(value);
+                            } else if name == "Avatar" && !value.is_empty() {
+                                ui.hyperlink(value.as_str());
+                            } else {
+                                ui.label(value.as_str());
+                            }
+                            ui.end_row();
+                        }
+
+                        ui.label("Read receipts");
+                        if editable {
+                            ui.checkbox(
+                                &mut profile.share_read_receipts,
+                                "Show others what I read",
+                            );
+                        } else {
+                            ui.label(match profile.share_read_receipts {
+                                true => "Shared",
+                                false => "Hidden",
+                            });
+                        }
+                        ui.end_row();
+                    });
+
+                if editable {
+                    save = ui.button("Save").clicked();
+                } else {
+                    // The server decides whether we are allowed to do this
+                    let username = profile.username.clone();
+                    ui.horizontal(|ui| {
+                        if ui.button("Kick").clicked() {
+                            moderate = Some(Request::Kick(username.clone(), String::new()));
+                        }
+                        if ui.button("Ban").clicked() {
+                            moderate = Some(Request::Ban(username.clone(), String::new()));
+                        }
+                        if profile.role == Role::Muted {
+                            if ui.button("Unmute").clicked() {
+                                moderate = Some(Request::Unmute(username.clone()));
+                            }
+                        } else if ui.button("Mute").clicked() {
+                            moderate = Some(Request::Mute(username.clone()));
+                        }
+                    });
+                }
+
+                if let Some(error) = &self.profile_error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+            });
+
+        if save {
+            self.profile_error = self
+                .runtime
+                .block_on(self.session.chat.update_profile(profile.clone()))
+                .err();
+        }
+
+        if let Some(request) = moderate {
+            let username = profile.username.clone();
+            match self.runtime.block_on(self.session.chat.request(request)) {
+                Err(e) => self.profile_error = Some(e),
+                Ok(_) => self.open_profile(&username),
+            }
+            return;
+        }
+
+        // A rejected profile stays open with the error
+        let saved = save && self.profile_error.is_none();
+        if saved {
+            self.update_zone();
+        }
+
+        if !open || saved {
+            self.profile = None;
+        }
+    }
+}
+
+impl eframe::App for App {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        self.settings.last_channel = self.session.channel.clone();
+        eframe::set_value(storage, eframe::APP_KEY, &self.settings);
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        // Every server we are logged in to, not only the one on screen
+        self.session.close(&self.runtime);
+        for (index, session) in self.sessions.iter_mut().enumerate() {
+            if index != self.active {
+                session.close(&self.runtime);
+            }
+        }
     }
 
     /// Called each time the UI needs repainting, which may be many times per second.
@@ -64,29 +1368,116 @@ impl eframe::App for App {
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
         // self.update_data();
 
+        self.poll_connect();
+        if self.login.is_some() {
+            self.login_screen(ctx);
+            return;
+        }
+
+        // Ctrl+F searches the messages
+        if ctx.input(|i| i.modifiers.command && i.key_pressed(egui::Key::F)) {
+            self.filter_open = true;
+        }
+
+        // Keep notifying about new messages while the window is idle
+        self.notify(ctx);
+        ctx.request_repaint_after(Duration::from_secs(1));
+
+        if self.session.try_reconnect(&self.runtime) {
+            self.update_interval = 0.0;
+        }
+        let connected = self.session.chat.is_connected();
+        if !connected {
+            // Check on a reconnect under way every so often
+            let wait = match self.session.reconnecting {
+                Some(_) => Duration::from_millis(100),
+                None => self
+                    .session
+                    .retry_at
+                    .saturating_duration_since(Instant::now()),
+            };
+            ctx.request_repaint_after(wait);
+        }
+
+        // Servers in the background reconnect on their own schedule
+        for (index, session) in self.sessions.iter_mut().enumerate() {
+            if index != self.active {
+                session.try_reconnect(&self.runtime);
+            }
+        }
+
         // After the interval, we will send a request to the server to get the latest messages.
         self.update_interval -= egui::InputState::default().unstable_dt;
-        if self.update_interval <= 0.0 {
+        if self.update_interval <= 0.0 && connected {
             self.update_interval = 1.0;
 
-            // I really want to make these multithreaded/async but dont want to
-            // change the connection to an arc<Mutex<TcpStream>>. I hope to
-            // figure this out later.
+            // Update unread counts. Where we had read up to when the
+            // channel was opened is where new messages start.
+            if let Ok(unread) = self.runtime.block_on(self.session.chat.unread()) {
+                self.session.unread = unread;
+
+                if self.session.divider.is_none() {
+                    self.session.divider = self
+                        .session
+                        .unread
+                        .iter()
+                        .find(|u| u.channel == self.session.channel)
+                        .map(|u| u.last_read);
+                }
+            }
 
             // Update Messages
-            let request = Request::GetMessages();
-            let response: Response = network::send_get(request, Arc::clone(&self.connection));
+            match self
+                .runtime
+                .block_on(self.session.chat.history(&self.session.channel))
+            {
+                Ok(messages) => self.session.message_list = messages,
+                Err(e) => self.session.command_output = Some(Err(e)),
+            }
+
+            // Everything in the channel is on screen now
+            if let Some(last) = self.session.message_list.last() {
+                if last.id > self.session.marked_read {
+                    let marked = self
+                        .runtime
+                        .block_on(self.session.chat.mark_read(&self.session.channel, last.id));
+                    if marked.is_ok() {
+                        self.session.marked_read = last.id;
+                    }
+                }
+            }
 
-            if let Response::Messages(messages) = response {
-                self.message_list = messages;
+            // Update mentions
+            if let Ok(mentions) = self.runtime.block_on(self.session.chat.mentions(50)) {
+                self.session.mentions = mentions;
+            }
+
+            // Update who is typing
+            if let Ok(users) = self
+                .runtime
+                .block_on(self.session.chat.who_is_typing(&self.session.channel))
+            {
+                self.session.typing_users = users;
+            }
+
+            // Update Channels
+            if let Ok(channels) = self.runtime.block_on(self.session.chat.channels()) {
+                self.session.channel_list = channels;
             }
 
             // Update Users
-            let request = Request::GetUsers();
-            let response: Response = network::send_get(request, Arc::clone(&self.connection));
+            if let Ok(users) = self.runtime.block_on(self.session.chat.users()) {
+                self.session.user_list = users;
+            }
 
-            if let Response::Users(users) = response {
-                self.user_list = users;
+            // Unread counts for the servers in the background
+            for (index, session) in self.sessions.iter_mut().enumerate() {
+                if index == self.active {
+                    continue;
+                }
+                if let Ok(unread) = self.runtime.block_on(session.chat.unread()) {
+                    session.unread = unread;
+                }
             }
         }
 
@@ -95,6 +1486,14 @@ impl eframe::App for App {
             // The top panel is often a good place for a menu bar:
             egui::menu::bar(ui, |ui| {
                 egui::menu::menu_button(ui, "File", |ui| {
+                    if ui.button("Settings").clicked() {
+                        self.settings_open = true;
+                        ui.close_menu();
+                    }
+                    if ui.button("Log out").clicked() {
+                        self.logout();
+                        ui.close_menu();
+                    }
                     if ui.button("Quit").clicked() {
                         frame.close();
                     }
@@ -102,10 +1501,16 @@ impl eframe::App for App {
 
                 ui.separator();
 
+                if ui.button("Search").clicked() {
+                    self.filter_open = !self.filter_open;
+                }
+
+                ui.separator();
+
                 egui::menu::menu_button(ui, "Help", |ui| {
                     if ui.button("About").clicked() {
-                        // This should open a new window with the about information.
-                        frame.close();
+                        self.open_about();
+                        ui.close_menu();
                     }
                 });
 
@@ -114,18 +1519,114 @@ impl eframe::App for App {
                 // Float this menu to the right
                 ui.with_layout(egui::Layout::right_to_left(Align::Center), |ui| {
                     egui::warn_if_debug_build(ui);
+
+                    if !connected {
+                        let wait = self
+                            .session
+                            .retry_at
+                            .saturating_duration_since(Instant::now());
+                        let status = match self.session.reconnecting {
+                            Some(_) => "Offline, reconnecting...".to_owned(),
+                            None => format!("Offline, reconnecting in {}s", wait.as_secs() + 1),
+                        };
+                        ui.colored_label(ui.visuals().warn_fg_color, status);
+                    }
                 });
             });
         });
 
+        // One tab for each server we are logged in to
+        egui::TopBottomPanel::top("server_tabs").show(ctx, |ui| self.server_tabs(ui));
+
         // This panel is meant to show the currently connected users.
         egui::SidePanel::left("user_panel").show(ctx, |ui| {
+            ui.heading("Channels");
+
+            ui.separator();
+
+            let mut switch = None;
+            let mut mute = None;
+            for channel in &self.session.channel_list {
+                let selected = channel.name == self.session.channel;
+                let unread = self
+                    .session
+                    .unread
+                    .iter()
+                    .find(|u| u.channel == channel.name)
+                    .map_or(0, |u| u.count);
+
+                let label = match unread {
+                    0 => channel.name.clone(),
+                    _ if selected => channel.name.clone(),
+                    n => format!("{} ({})", channel.name, n),
+                };
+
+                let server = self.session.chat.address();
+                let muted = self.settings.notifications.is_muted(server, &channel.name);
+                let label = match muted {
+                    true => format!("{} 🔕", label),
+                    false => label,
+                };
+
+                let button = ui.selectable_label(selected, label);
+                if button.clicked() {
+                    switch = Some(channel.name.clone());
+                }
+                button.context_menu(|ui| {
+                    let text = if muted { "Unmute" } else { "Mute" };
+                    if ui.button(text).clicked() {
+                        mute = Some(channel.name.clone());
+                        ui.close_menu();
+                    }
+                });
+            }
+
+            if let Some(channel) = mute {
+                let server = self.session.chat.address();
+                self.settings.notifications.toggle_mute(server, &channel);
+            }
+
+            if let Some(channel) = switch {
+                self.switch_channel(channel);
+            }
+
+            ui.separator();
+
             ui.heading("Users");
 
             ui.separator();
 
-            for user in &self.user_list {
-                ui.label(user.to_string());
+            // Clicking a user opens their profile
+            let mut clicked = None;
+            for user in &self.session.user_list {
+                if ui.selectable_label(false, user.to_string()).clicked() {
+                    clicked = Some(user.username.clone());
+                }
+            }
+
+            if let Some(username) = clicked {
+                self.open_profile(&username);
+            }
+
+            ui.separator();
+
+            // Mentions in messages we have not read yet
+            let unread = self
+                .session
+                .mentions
+                .iter()
+                .filter(|m| {
+                    let last_read = self.session.unread.iter().find(|u| u.channel == m.channel);
+                    m.id > last_read.map_or(0, |u| u.last_read)
+                })
+                .count();
+
+            let label = match unread {
+                0 => "Mentions".to_owned(),
+                n => format!("Mentions ({})", n),
+            };
+            if ui.selectable_label(self.mentions_open, label).clicked() {
+                self.mentions_open = !self.mentions_open;
             }
 
             // ui.horizontal(|ui| {
@@ -153,7 +1654,18 @@ impl eframe::App for App {
         egui::CentralPanel::default().show(ctx, |ui| {
             // The central panel the region left after adding TopPanel's and SidePanel's
 
-            ui.heading("Messages");
+            ui.heading(&self.session.channel);
+
+            if let Some(channel) = self
+                .session
+                .channel_list
+                .iter()
+                .find(|c| c.name == self.session.channel)
+            {
+                if !channel.topic.is_empty() {
+                    ui.label(&channel.topic);
+                }
+            }
 
             ui.separator();
 
@@ -171,44 +1683,214 @@ impl eframe::App for App {
             //     "Source code."
             // ));
 
+            let users: Vec<String> = self
+                .session
+                .user_list
+                .iter()
+                .map(|u| u.username.clone())
+                .collect();
+            let completions = command::complete(
+                &self.session.message_box_value,
+                &self.all_commands(),
+                &users,
+            );
+
+            // Leave room for the message box, completions and command output
+            let extra_lines = match &self.session.command_output {
+                Some(Ok(text)) => text.lines().count(),
+                Some(Err(_)) => 1,
+                None => 0,
+            } + usize::from(!completions.is_empty())
+                + usize::from(!self.session.typing_users.is_empty());
+
+            let mut seen_by = None;
+            let mut scrolled = false;
+
+            if self.filter_open {
+                self.filter_bar(ui);
+                ui.separator();
+            }
+
             ui.vertical(|ui| {
-                ui.set_max_height(ui.available_height() - 25.0);
+                ui.set_max_height(ui.available_height() - 25.0 - extra_lines as f32 * 18.0);
 
                 egui::scroll_area::ScrollArea::new([false, true])
                     // .max_width(f32::INFINITY)
                     .show(ui, |ui| {
                         ui.set_width(ui.available_width());
 
-                        for message in &self.message_list {
-                            ui.label(message.to_string());
+                        // While searching only the matches are shown
+                        let username = &self.session.username;
+                        let filtering = self.filter_open && !self.filter.is_empty();
+                        let shown: Vec<&Message> = self
+                            .session
+                            .message_list
+                            .iter()
+                            .filter(|m| !filtering || self.filter.matches(m, username, &self.clock))
+                            .collect();
+                        let highlight = if filtering {
+                            self.filter.text.trim()
+                        } else {
+                            ""
+                        };
+                        let current = match filtering {
+                            true => Some(self.filter_current.min(shown.len().saturating_sub(1))),
+                            false => None,
+                        };
+
+                        // The divider goes before the first new message by someone else,
+                        // unless everything in the channel is new
+                        let divider = self.session.divider.and_then(|divider| {
+                            shown
+                                .iter()
+                                .position(|m| m.id > divider && m.username != *username)
+                                .filter(|&index| index > 0)
+                        });
+
+                        let now = database::current_timestamp();
+                        let mut day = None;
+
+                        for (index, message) in shown.iter().copied().enumerate() {
+                            // A separator where a new day starts
+                            let message_day = self.clock.day(message.timestamp_ms);
+                            let mut continued = index > 0
+                                && divider != Some(index)
+                                && render::continues(shown[index - 1], message);
+
+                            if day != Some(message_day) {
+                                continued = false;
+                                day = Some(message_day);
+                                let label = self.clock.day_label(message.timestamp_ms, now);
+                                render::day_separator(ui, &label);
+                            }
+
+                            if divider == Some(index) {
+                                ui.horizontal(|ui| {
+                                    ui.colored_label(ui.visuals().warn_fg_color, "New messages");
+                                    ui.separator();
+                                });
+                            }
+
+                            // The current match, messages that mention us and our own stand out
+                            let frame = if current == Some(index) {
+                                egui::Frame::none()
+                                    .fill(ui.visuals().selection.bg_fill.linear_multiply(0.6))
+                            } else if message.mentions(username) {
+                                egui::Frame::none()
+                                    .fill(ui.visuals().selection.bg_fill.linear_multiply(0.3))
+                            } else if message.username == *username {
+                                egui::Frame::none().fill(ui.visuals().faint_bg_color)
+                            } else {
+                                egui::Frame::none()
+                            };
+                            let label = frame
+                                .show(ui, |ui| {
+                                    ui.set_width(ui.available_width());
+                                    render::message(ui, message, &self.clock, continued, highlight)
+                                })
+                                .inner;
+
+                            if current == Some(index) && self.filter_scroll {
+                                label.scroll_to_me(Some(Align::Center));
+                                scrolled = true;
+                            }
+
+                            label.context_menu(|ui| {
+                                if ui.button("Seen by").clicked() {
+                                    seen_by = Some(message.id);
+                                    ui.close_menu();
+                                }
+                            });
+                        }
+
+                        // Messages waiting for the connection, drawn like our others
+                        let mut previous = shown.last().copied();
+                        let channel = &self.session.channel;
+                        for message in self.session.outbox.iter().filter(|m| m.channel == *channel)
+                        {
+                            let continued = previous.is_some_and(|p| render::continues(p, message));
+                            egui::Frame::none()
+                                .fill(ui.visuals().faint_bg_color)
+                                .show(ui, |ui| {
+                                    ui.set_width(ui.available_width());
+                                    render::message(ui, message, &self.clock, continued, "");
+                                    ui.weak("Pending, sent when we are back online");
+                                });
+                            previous = Some(message);
                         }
                     });
             });
 
+            if let Some(id) = seen_by {
+                self.open_readers(id);
+            }
+            if scrolled {
+                self.filter_scroll = false;
+            }
+
+            if let Some(text) = typing::describe(&self.session.typing_users) {
+                ui.weak(text);
+            }
+
             ui.separator();
 
             // This panel is meant to sent messages to the server.
             // egui::TopBottomPanel::bottom("input_area").show(ctx, |ui| {
             ui.horizontal(|ui| {
-                ui.add(
-                    TextEdit::singleline(&mut self.message_box_value)
-                        .hint_text("Enter your message"), // .clip_text(true),
+                let response = ui.add(
+                    TextEdit::singleline(&mut self.session.message_box_value)
+                        .hint_text("Enter your message, or /help")
+                        .lock_focus(true), // .clip_text(true),
                 );
 
-                // When the user presses enter, we send the message to the server.
-                if ui.button("send it").clicked() {
-                    let message =
-                        Message::new(self.username.clone(), self.message_box_value.clone());
-                    let request = Request::AddMessage(message);
+                // Tab completes commands and usernames
+                if response.has_focus() && ui.input(|i| i.key_pressed(egui::Key::Tab)) {
+                    if let Some(completion) = completions.first() {
+                        self.session.message_box_value = completion.clone();
+                        move_cursor_to_end(ui, response.id, completion);
+                    }
+                }
 
-                    self.message_box_value = String::new();
+                let entered =
+                    response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
 
-                    network::send(request, Arc::clone(&self.connection));
-                    let _response: Option<Response> = network::get(Arc::clone(&self.connection));
+                // When the user presses enter, we send the message to the server.
+                if ui.button("send it").clicked() || entered {
+                    self.submit();
+                    response.request_focus();
                 }
+
+                self.update_typing(response.changed(), response.has_focus());
             });
+
+            if !completions.is_empty() {
+                ui.horizontal_wrapped(|ui| {
+                    for completion in &completions {
+                        if ui.small_button(completion.trim()).clicked() {
+                            self.session.message_box_value = completion.clone();
+                        }
+                    }
+                });
+            }
+
+            match &self.session.command_output {
+                Some(Ok(text)) => {
+                    ui.label(text);
+                }
+                Some(Err(e)) => {
+                    ui.colored_label(ui.visuals().error_fg_color, e);
+                }
+                None => {}
+            }
             // });
         });
+
+        self.profile_window(ctx);
+        self.readers_window(ctx);
+        self.mentions_window(ctx);
+        self.settings_window(ctx);
+        self.about_window(ctx);
+
         // // This is just an example of how to use the `egui::Window` widget.
         // if false {
         //     egui::Window::new("Window").show(ctx, |ui| {
@@ -220,3 +1902,29 @@ impl eframe::App for App {
         // }
     }
 }
+
+/// Puts the text cursor after the text, e.g. after completing it.
+fn move_cursor_to_end(ui: &egui::Ui, id: egui::Id, text: &str) {
+    if let Some(mut state) = egui::TextEdit::load_state(ui.ctx(), id) {
+        let cursor = egui::text::CCursor::new(text.chars().count());
+        state.set_ccursor_range(Some(egui::text::CCursorRange::one(cursor)));
+        state.store(ui.ctx(), id);
+    }
+}
+
+// A number of bytes for people, like "1.5 MiB"
+fn describe_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", size, UNITS[unit]),
+    }
+}
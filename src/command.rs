@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+
+/// CommandInfo describes a slash command, e.g. for /help and autocomplete.
+/// The server advertises its commands to clients with these.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CommandInfo {
+    pub name: String,  // Without the slash
+    pub usage: String, // e.g. <user> [reason]
+    pub help: String,
+}
+
+impl CommandInfo {
+    pub fn new(name: &str, usage: &str, help: &str) -> CommandInfo {
+        CommandInfo {
+            name: name.to_owned(),
+            usage: usage.to_owned(),
+            help: help.to_owned(),
+        }
+    }
+
+    /// Returns true if the first argument is a username
+    pub fn takes_user(&self) -> bool {
+        self.usage.starts_with("<user>")
+    }
+}
+
+/// Registry maps command names to handlers. The client and the server
+/// each keep one with their own handler type.
+#[derive(Debug)]
+pub struct Registry<H> {
+    commands: Vec<(CommandInfo, H)>,
+}
+
+impl<H> Default for Registry<H> {
+    fn default() -> Self {
+        Registry { commands: vec![] }
+    }
+}
+
+impl<H> Registry<H> {
+    /// Adds a command, replacing any command with the same name.
+    pub fn register(&mut self, info: CommandInfo, handler: H) {
+        self.commands
+            .retain(|(existing, _)| existing.name != info.name);
+        self.commands.push((info, handler));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&H> {
+        self.commands
+            .iter()
+            .find(|(info, _)| info.name == name)
+            .map(|(_, handler)| handler)
+    }
+
+    pub fn infos(&self) -> Vec<CommandInfo> {
+        self.commands.iter().map(|(info, _)| info.clone()).collect()
+    }
+}
+
+/// Splits "/name arguments" into the name and the trimmed arguments.
+/// Returns None if the input is not a command. "//text" escapes a slash.
+pub fn parse(input: &str) -> Option<(&str, &str)> {
+    let input = input.trim_start();
+    let rest = input.strip_prefix('/')?;
+
+    if rest.is_empty() || rest.starts_with('/') || rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    match rest.split_once(char::is_whitespace) {
+        Some((name, args)) => Some((name, args.trim())),
+        None => Some((rest, "")),
+    }
+}
+
+/// Splits the first word off the arguments, e.g. "bob being rude" into ("bob", "being rude").
+pub fn split_first(args: &str) -> (&str, &str) {
+    match args.trim().split_once(char::is_whitespace) {
+        Some((first, rest)) => (first, rest.trim()),
+        None => (args.trim(), ""),
+    }
+}
+
+/// Returns the completions for a partially typed command line.
+/// While the name is being typed commands are completed, after it users are.
+pub fn complete(input: &str, commands: &[CommandInfo], users: &[String]) -> Vec<String> {
+    let Some(rest) = input.strip_prefix('/') else {
+        return vec![];
+    };
+
+    match rest.split_once(' ') {
+        None => commands
+            .iter()
+            .filter(|info| info.name.starts_with(rest))
+            .map(|info| format!("/{} ", info.name))
+            .collect(),
+        Some((name, arg)) if !arg.contains(' ') => {
+            let takes_user = commands
+                .iter()
+                .any(|info| info.name == name && info.takes_user());
+
+            if !takes_user {
+                return vec![];
+            }
+
+            users
+                .iter()
+                .filter(|user| user.starts_with(arg) && user.as_str() != arg)
+                .map(|user| format!("/{} {} ", name, user))
+                .collect()
+        }
+        Some(_) => vec![],
+    }
+}
+
+//
+// Test Cases
+#[test]
+fn test_parse() {
+    assert_eq!(
+        parse("/kick bob  being rude "),
+        Some(("kick", "bob  being rude"))
+    );
+    assert_eq!(parse("/help"), Some(("help", "")));
+    assert_eq!(parse("hello"), None);
+    assert_eq!(parse("//not a command"), None);
+    assert_eq!(parse("/ spaced"), None);
+
+    assert_eq!(split_first("bob  being rude"), ("bob", "being rude"));
+    assert_eq!(split_first("bob"), ("bob", ""));
+}
+
+#[test]
+fn test_complete() {
+    let commands = vec![
+        CommandInfo::new("kick", "<user> [reason]", ""),
+        CommandInfo::new("join", "<channel>", ""),
+        CommandInfo::new("me", "<action>", ""),
+    ];
+    let users = vec!["bob".to_string(), "bea".to_string()];
+
+    assert_eq!(complete("/j", &commands, &users), vec!["/join "]);
+    assert_eq!(complete("/kick b", &commands, &users).len(), 2);
+    assert_eq!(complete("/kick bo", &commands, &users), vec!["/kick bob "]);
+    assert!(complete("/join b", &commands, &users).is_empty());
+    assert!(complete("hello", &commands, &users).is_empty());
+}
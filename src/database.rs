@@ -1,3 +1,5 @@
+use std::path::{Path, PathBuf};
+
 use turbosql::Turbosql;
 
 #[derive(Turbosql, Default, Debug, Clone)]
@@ -6,22 +8,133 @@ pub struct MessageRow {
     pub username: Option<String>,
     pub content: Option<String>,
     pub timestamp_ms: Option<i64>,
+    pub channel: Option<String>,
+    pub pinned: Option<bool>,
+    pub mentions: Option<String>, // Space separated usernames
 }
 
 #[derive(Turbosql, Default, Debug, Clone)]
 pub struct UserRow {
     pub rowid: Option<i64>,
     pub username: Option<String>,
+    pub display_name: Option<String>,
+    pub status: Option<String>,
+    pub avatar: Option<String>,
+    pub timezone: Option<String>,
+    pub online: Option<bool>,
+    pub role: Option<String>,
+    pub share_read_receipts: Option<bool>, // Shared unless turned off
+    pub token: Option<String>,             // Given out on the first login, see `user::add_user`
+}
+
+#[derive(Turbosql, Default, Debug, Clone)]
+pub struct ChannelRow {
+    pub rowid: Option<i64>,
+    pub name: Option<String>,
+    pub topic: Option<String>,
+    pub topic_set_by: Option<String>,
+}
+
+#[derive(Turbosql, Default, Debug, Clone)]
+pub struct BanRow {
+    pub rowid: Option<i64>,
+    pub username: Option<String>,
+    pub address: Option<String>,
+    pub reason: Option<String>,
+    pub banned_by: Option<String>,
+    pub timestamp_ms: Option<i64>,
+}
+
+#[derive(Turbosql, Default, Debug, Clone)]
+pub struct AuditRow {
+    pub rowid: Option<i64>,
+    pub action: Option<String>,
+    pub actor: Option<String>,
+    pub target: Option<String>,
+    pub reason: Option<String>,
+    pub timestamp_ms: Option<i64>,
+}
+
+// The last message a user has seen in a channel
+#[derive(Turbosql, Default, Debug, Clone)]
+pub struct ReadRow {
+    pub rowid: Option<i64>,
+    pub username: Option<String>,
+    pub channel: Option<String>,
+    pub last_read: Option<i64>, // Message id
 }
 
 pub fn current_timestamp() -> i64 {
     chrono::Utc::now().timestamp_millis()
 }
 
+/// The file turbosql uses when no path is given,
+/// e.g. ~/.local/share/rust_chatter/rust_chatter.sqlite
+pub fn default_path() -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    let stem = exe.file_stem().unwrap().to_string_lossy();
+
+    directories_next::ProjectDirs::from("org", &stem, &stem)
+        .unwrap()
+        .data_dir()
+        .join(stem.as_ref())
+        .with_extension("sqlite")
+}
+
+/// Points turbosql at a database file. Must be called before any query.
+pub fn set_path(path: &Path) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+
+    turbosql::set_db_path(path)
+        .map_err(|_| "The database is already open, its path can no longer be changed".to_owned())
+}
+
+/// Writes everything in the write-ahead log into the database file.
+pub fn checkpoint() {
+    if let Err(e) = turbosql::checkpoint() {
+        eprintln!("Could not checkpoint the database: {}", e);
+    }
+}
+
+/// DatabasePath picks the file the database lives in. turbosql has one
+/// database per process, shared by every server in it, so the path can
+/// only be chosen before the first query.
+#[derive(Debug, Clone, Default)]
+pub enum DatabasePath {
+    /// Whatever database is already in use, or the default file
+    #[default]
+    Current,
+    /// A SQLite file, created if missing
+    File(PathBuf),
+    /// A new file in the temp directory, e.g. for tests
+    Temporary,
+}
+
+impl DatabasePath {
+    /// Points the database at the path. Fails if the database is
+    /// already open, rather than quietly using the open one.
+    pub fn open(&self) -> Result<(), String> {
+        match self {
+            DatabasePath::Current => Ok(()),
+            DatabasePath::File(path) => set_path(path),
+            DatabasePath::Temporary => {
+                let name = format!(
+                    "rust_chatter-{}-{}.sqlite",
+                    std::process::id(),
+                    current_timestamp()
+                );
+                set_path(&std::env::temp_dir().join(name))
+            }
+        }
+    }
+}
+
 // Message Namespace
 pub mod message {
     use super::MessageRow;
-    use crate::message::Message;
+    use crate::message::{Message, DEFAULT_CHANNEL};
     use turbosql::{execute, select, Turbosql};
 
     /// Creates a new message
@@ -47,6 +160,21 @@ pub mod message {
         message.insert().unwrap();
     }
 
+    // Add a message to the database, keeping its timestamp. Returns false
+    // if the same message is already there, e.g. from an earlier import.
+    pub fn import_message(message: Message) -> bool {
+        let (username, timestamp_ms, content) =
+            (&message.username, message.timestamp_ms, &message.content);
+        let count = select!(i64 "COUNT(*) FROM messagerow WHERE username = ? AND timestamp_ms = ? AND content = ?", username, timestamp_ms, content)
+            .unwrap();
+
+        if count > 0 {
+            return false;
+        }
+        message.to_row().insert().unwrap();
+        true
+    }
+
     // From MessageRow to Message
     impl From<MessageRow> for Message {
         fn from(row: MessageRow) -> Self {
@@ -55,6 +183,15 @@ pub mod message {
                 username: row.username.unwrap(),
                 content: row.content.unwrap(),
                 timestamp_ms: row.timestamp_ms.unwrap(),
+                // Rows written before channels existed have no channel
+                channel: row.channel.unwrap_or_else(|| DEFAULT_CHANNEL.to_owned()),
+                pinned: row.pinned.unwrap_or(false),
+                mentions: row
+                    .mentions
+                    .unwrap_or_default()
+                    .split_whitespace()
+                    .map(str::to_owned)
+                    .collect(),
             }
         }
     }
@@ -69,16 +206,80 @@ pub mod message {
         select!(Vec<MessageRow>).unwrap()
     }
 
+    // Select the messages of one channel
+    pub fn select_channel(channel: &str) -> Vec<MessageRow> {
+        select!(Vec<MessageRow> "WHERE IFNULL(channel, 'general') = ? ORDER BY timestamp_ms", channel)
+            .unwrap()
+    }
+
+    // Select the names of every channel with messages
+    pub fn select_channel_names() -> Vec<String> {
+        select!(Vec<String> "DISTINCT IFNULL(channel, 'general') FROM messagerow").unwrap()
+    }
+
+    // Select the messages that mention the user, newest first. The name is
+    // matched exactly against the space separated mentions, not as a pattern.
+    pub fn select_mentions(username: &str) -> Vec<MessageRow> {
+        select!(Vec<MessageRow> "WHERE INSTR(' ' || mentions || ' ', ' ' || ? || ' ') > 0 ORDER BY timestamp_ms DESC", username)
+            .unwrap()
+    }
+
     // Select a message after timestamp
     pub fn select_after(timestamp_ms: u64) -> Vec<MessageRow> {
         select!(Vec<MessageRow> "WHERE timestamp_ms >" timestamp_ms).unwrap()
     }
 
+    // Select messages in [since, until)
+    pub fn select_between(since_ms: i64, until_ms: i64) -> Vec<MessageRow> {
+        select!(Vec<MessageRow> "WHERE timestamp_ms >=" since_ms "AND timestamp_ms <" until_ms "ORDER BY timestamp_ms")
+            .unwrap()
+    }
+
     // Select most recent message
     pub fn select_last(i: u32) -> Vec<MessageRow> {
         select!(Vec<MessageRow> "ORDER BY timestamp_ms DESC LIMIT" i).unwrap()
     }
 
+    // Select unpinned messages older than the timestamp
+    pub fn select_before(timestamp_ms: i64) -> Vec<MessageRow> {
+        select!(Vec<MessageRow> "WHERE timestamp_ms <" timestamp_ms "AND NOT IFNULL(pinned, 0)")
+            .unwrap()
+    }
+
+    // Select unpinned messages beyond the newest `max` of each channel
+    pub fn select_over_limit(max: u32) -> Vec<MessageRow> {
+        select!(Vec<MessageRow> "WHERE rowid IN (
+            SELECT rowid FROM (
+                SELECT rowid, ROW_NUMBER() OVER (
+                    PARTITION BY IFNULL(channel," DEFAULT_CHANNEL ") ORDER BY timestamp_ms DESC
+                ) AS position
+                FROM messagerow
+                WHERE NOT IFNULL(pinned, 0)
+            ) WHERE position >" max ")")
+        .unwrap()
+    }
+
+    // Pin or unpin a message
+    pub fn set_pinned(id: i64, pinned: bool) {
+        execute!(
+            "UPDATE messagerow SET pinned = ? WHERE rowid = ?",
+            pinned,
+            id
+        )
+        .unwrap();
+    }
+
+    // Delete a single message, unless it was pinned in the meantime.
+    // Returns whether it was deleted.
+    pub fn delete_unpinned(id: i64) -> bool {
+        let deleted = execute!(
+            "DELETE FROM messagerow WHERE rowid = ? AND NOT IFNULL(pinned, 0)",
+            id
+        )
+        .unwrap();
+        deleted > 0
+    }
+
     // Delete all messages
     #[allow(dead_code)]
     pub fn delete_all() {
@@ -89,20 +290,115 @@ pub mod message {
 // User Namespace
 pub mod user {
     use super::UserRow;
-    use crate::message::User;
+    use crate::message::{is_valid_username, Profile, Role, User};
     use turbosql::{execute, select, Turbosql};
 
-    pub fn add_user(user: User) {
+    /// Marks the user as online, creating them on their first login.
+    /// Fails if someone is already online with that username, or if the
+    /// username has a token and the user did not bring it. Returns the
+    /// token, which users from before tokens get on their next login.
+    pub fn add_user(user: User) -> Result<String, String> {
+        let username = user.username.trim().to_owned();
+
+        if !is_valid_username(&username) {
+            return Err(format!(
+                "Invalid username {:?}, use letters, digits, _ - and .",
+                username
+            ));
+        }
+
+        // Claiming the name is a single statement, so two logins can't both win
+        let in_use = || format!("Username {} is already in use", username);
+        let new_token = new_token();
+        let claimed = execute!(
+            "UPDATE userrow SET online = 1, token = IFNULL(token, ?)
+            WHERE username = ? AND NOT IFNULL(online, 0) AND IFNULL(token, ?) = ?",
+            new_token,
+            username,
+            user.token,
+            user.token
+        )
+        .unwrap();
+
+        match get(&username) {
+            Some(row) if claimed > 0 => return Ok(row.token.unwrap_or_default()),
+            Some(row) if row.token.as_ref().is_some_and(|token| *token != user.token) => {
+                return Err(format!("Username {} belongs to someone else", username))
+            }
+            Some(_) => return Err(in_use()),
+            None => {}
+        }
+
+        // The unique index on username turns a racing insert into an error
         UserRow {
-            username: Some(user.username),
+            username: Some(username.clone()),
+            online: Some(true),
+            token: Some(new_token.clone()),
             ..Default::default()
         }
         .insert()
+        .map(|_| new_token)
+        .map_err(|_| in_use())
+    }
+
+    // A secret that is hard to guess, for the user to log in with
+    fn new_token() -> String {
+        use rand::distributions::{Alphanumeric, DistString};
+        Alphanumeric.sample_string(&mut rand::thread_rng(), 32)
+    }
+
+    pub fn get(username: &str) -> Option<UserRow> {
+        select!(Option<UserRow> "WHERE username = ? ORDER BY rowid LIMIT 1", username).unwrap()
+    }
+
+    // Select users that are currently connected
+    pub fn select_online() -> Vec<UserRow> {
+        select!(Vec<UserRow> "WHERE online").unwrap()
+    }
+
+    pub fn set_online(username: &str, online: bool) {
+        execute!(
+            "UPDATE userrow SET online = ? WHERE username = ?",
+            online,
+            username
+        )
+        .unwrap();
+    }
+
+    // Nobody is connected when the server starts
+    pub fn clear_presence() {
+        execute!("UPDATE userrow SET online = 0").unwrap();
+    }
+
+    pub fn update_profile(profile: Profile) {
+        execute!(
+            "UPDATE userrow SET display_name = ?, status = ?, avatar = ?, timezone = ?,
+            share_read_receipts = ? WHERE username = ?",
+            profile.display_name,
+            profile.status,
+            profile.avatar,
+            profile.timezone,
+            profile.share_read_receipts,
+            profile.username
+        )
         .unwrap();
     }
 
-    pub fn select_all() -> Vec<UserRow> {
-        select!(Vec<UserRow>).unwrap()
+    // Users without a role are members
+    pub fn role(username: &str) -> Role {
+        get(username)
+            .and_then(|row| row.role)
+            .and_then(|role| role.parse().ok())
+            .unwrap_or_default()
+    }
+
+    pub fn set_role(username: &str, role: Role) {
+        execute!(
+            "UPDATE userrow SET role = ? WHERE username = ?",
+            role.to_string(),
+            username
+        )
+        .unwrap();
     }
 
     // From UserRow to User
@@ -111,6 +407,25 @@ pub mod user {
             User {
                 id: row.rowid.unwrap() as i32,
                 username: row.username.unwrap(),
+                token: String::new(), // Only ever given to the user themselves
+            }
+        }
+    }
+
+    // From UserRow to Profile
+    impl From<UserRow> for Profile {
+        fn from(row: UserRow) -> Self {
+            Profile {
+                username: row.username.unwrap(),
+                display_name: row.display_name.unwrap_or_default(),
+                status: row.status.unwrap_or_default(),
+                avatar: row.avatar.unwrap_or_default(),
+                timezone: row.timezone.unwrap_or_default(),
+                role: row
+                    .role
+                    .and_then(|role| role.parse().ok())
+                    .unwrap_or_default(),
+                share_read_receipts: row.share_read_receipts.unwrap_or(true),
             }
         }
     }
@@ -124,9 +439,179 @@ pub mod user {
     pub fn delete_all() {
         execute!("DELETE FROM USERROW").unwrap();
     }
+}
+
+// Channel Namespace
+pub mod channel {
+    use super::ChannelRow;
+    use crate::message::Channel;
+    use turbosql::{execute, select, Turbosql};
+
+    pub fn get(name: &str) -> Option<ChannelRow> {
+        select!(Option<ChannelRow> "WHERE name = ? LIMIT 1", name).unwrap()
+    }
+
+    pub fn set_topic(name: &str, topic: &str, set_by: &str) {
+        if get(name).is_some() {
+            execute!(
+                "UPDATE channelrow SET topic = ?, topic_set_by = ? WHERE name = ?",
+                topic,
+                set_by,
+                name
+            )
+            .unwrap();
+            return;
+        }
+
+        ChannelRow {
+            name: Some(name.to_owned()),
+            topic: Some(topic.to_owned()),
+            topic_set_by: Some(set_by.to_owned()),
+            ..Default::default()
+        }
+        .insert()
+        .unwrap();
+    }
+
+    // Every channel that has messages or a topic
+    pub fn select_all() -> Vec<Channel> {
+        let mut names = super::message::select_channel_names();
+        names.extend(select!(Vec<String> "name FROM channelrow").unwrap());
+        names.sort();
+        names.dedup();
+
+        let rows = select!(Vec<ChannelRow>).unwrap();
+
+        names
+            .into_iter()
+            .map(|name| Channel {
+                topic: rows
+                    .iter()
+                    .find(|row| row.name.as_ref() == Some(&name))
+                    .and_then(|row| row.topic.clone())
+                    .unwrap_or_default(),
+                name,
+            })
+            .collect()
+    }
+}
+
+// Moderation Namespace
+pub mod moderation {
+    use super::{AuditRow, BanRow};
+    use crate::message::{AuditEntry, Ban};
+    use turbosql::{execute, select, Turbosql};
+
+    pub fn add_ban(ban: Ban) {
+        BanRow {
+            username: Some(ban.username),
+            address: Some(ban.address),
+            reason: Some(ban.reason),
+            banned_by: Some(ban.banned_by),
+            timestamp_ms: Some(ban.timestamp_ms),
+            ..Default::default()
+        }
+        .insert()
+        .unwrap();
+    }
+
+    pub fn remove_ban(username: &str) {
+        execute!("DELETE FROM banrow WHERE username = ?", username).unwrap();
+    }
+
+    pub fn is_username_banned(username: &str) -> bool {
+        select!(i64 "COUNT(*) FROM banrow WHERE username = ?", username).unwrap() > 0
+    }
+
+    pub fn is_address_banned(address: &str) -> bool {
+        select!(i64 "COUNT(*) FROM banrow WHERE address = ?", address).unwrap() > 0
+    }
+
+    pub fn select_bans() -> Vec<Ban> {
+        select!(Vec<BanRow> "ORDER BY timestamp_ms DESC")
+            .unwrap()
+            .into_iter()
+            .map(|row| Ban {
+                username: row.username.unwrap_or_default(),
+                address: row.address.unwrap_or_default(),
+                reason: row.reason.unwrap_or_default(),
+                banned_by: row.banned_by.unwrap_or_default(),
+                timestamp_ms: row.timestamp_ms.unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    pub fn add_audit(entry: AuditEntry) {
+        AuditRow {
+            action: Some(entry.action),
+            actor: Some(entry.actor),
+            target: Some(entry.target),
+            reason: Some(entry.reason),
+            timestamp_ms: Some(entry.timestamp_ms),
+            ..Default::default()
+        }
+        .insert()
+        .unwrap();
+    }
 
-    pub fn remove(username: String) {
-        execute!("DELETE FROM USERROW WHERE username = ?", username).unwrap();
+    // Select the most recent audit entries
+    pub fn select_audit(i: u32) -> Vec<AuditEntry> {
+        select!(Vec<AuditRow> "ORDER BY timestamp_ms DESC LIMIT" i)
+            .unwrap()
+            .into_iter()
+            .map(|row| AuditEntry {
+                action: row.action.unwrap_or_default(),
+                actor: row.actor.unwrap_or_default(),
+                target: row.target.unwrap_or_default(),
+                reason: row.reason.unwrap_or_default(),
+                timestamp_ms: row.timestamp_ms.unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+// Read Marker Namespace
+pub mod read {
+    use super::ReadRow;
+    use turbosql::{execute, select};
+
+    // The id of the last message the user has seen in the channel, 0 if none
+    pub fn last_read(username: &str, channel: &str) -> i64 {
+        select!(Option<ReadRow> "WHERE username = ? AND channel = ? LIMIT 1", username, channel)
+            .unwrap()
+            .and_then(|row| row.last_read)
+            .unwrap_or_default()
+    }
+
+    // Markers only move forward, so an old client cannot unread messages.
+    // One statement, so two connections of the same user cannot both insert.
+    pub fn mark_read(username: &str, channel: &str, id: i64) {
+        execute!(
+            "INSERT INTO readrow (username, channel, last_read) VALUES (?, ?, ?)
+            ON CONFLICT (username, channel)
+            DO UPDATE SET last_read = MAX(IFNULL(last_read, 0), excluded.last_read)",
+            username,
+            channel,
+            id
+        )
+        .unwrap();
+    }
+
+    // Messages by others in the channel the user has not seen
+    pub fn count_unread(username: &str, channel: &str) -> u32 {
+        let last_read = last_read(username, channel);
+
+        select!(i64 "COUNT(*) FROM messagerow WHERE IFNULL(channel, 'general') = ? AND rowid > ? AND username != ?", channel, last_read, username)
+            .unwrap() as u32
+    }
+
+    // Everyone who has seen the message, apart from those who keep it to themselves
+    pub fn select_readers(channel: &str, id: i64) -> Vec<String> {
+        select!(Vec<String> "readrow.username FROM readrow
+            LEFT JOIN userrow ON userrow.username = readrow.username
+            WHERE channel = ? AND last_read >= ? AND IFNULL(share_read_receipts, 1)
+            ORDER BY readrow.username", channel, id)
+        .unwrap()
     }
 }
 
@@ -165,3 +650,19 @@ fn test_delete_all() {
     message::delete_all();
     user::delete_all();
 }
+
+#[test]
+fn test_mark_read_only_moves_forward() {
+    // The test database outlives the test, so use a new channel every run
+    let channel = &format!("read-test-{}", current_timestamp());
+
+    read::mark_read("read_test", channel, 5);
+    read::mark_read("read_test", channel, 3);
+    assert_eq!(read::last_read("read_test", channel), 5);
+
+    read::mark_read("read_test", channel, 8);
+    assert_eq!(read::last_read("read_test", channel), 8);
+
+    assert!(read::select_readers(channel, 8).contains(&"read_test".to_string()));
+    assert!(read::select_readers(channel, 9).is_empty());
+}
@@ -0,0 +1,130 @@
+use chrono::NaiveDate;
+use std::ops::Range;
+
+use crate::message::Message;
+use crate::timestamp::Clock;
+
+/// Filter narrows down the loaded messages. Empty fields match everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    pub text: String,   // Found anywhere in the content, ignoring case
+    pub author: String, // The start of the username, ignoring case
+    pub date: String,   // The day it was sent, written as 2023-04-01
+    pub only_mentions: bool,
+}
+
+impl Filter {
+    pub fn is_empty(&self) -> bool {
+        *self == Filter::default()
+    }
+
+    /// The day to filter by, or an error if it is not a date.
+    pub fn date(&self) -> Result<Option<NaiveDate>, String> {
+        let date = self.date.trim();
+        if date.is_empty() {
+            return Ok(None);
+        }
+
+        NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map(Some)
+            .map_err(|_| "Dates are written as 2023-04-01".to_owned())
+    }
+
+    /// Whether the message passes the filter, for `username`. Days are
+    /// those of the clock's timezone. A date that does not parse is ignored.
+    pub fn matches(&self, message: &Message, username: &str, clock: &Clock) -> bool {
+        if self.only_mentions && !message.mentions(username) {
+            return false;
+        }
+
+        if let Ok(Some(date)) = self.date() {
+            if clock.day(message.timestamp_ms) != date {
+                return false;
+            }
+        }
+
+        let author = self.author.trim();
+        if !author.is_empty() && match_at(&message.username, author).is_none() {
+            return false;
+        }
+
+        self.text.trim().is_empty() || !find(&message.content, self.text.trim()).is_empty()
+    }
+}
+
+/// Where `needle` is found in `text`, ignoring case, as byte ranges of `text`.
+pub fn find(text: &str, needle: &str) -> Vec<Range<usize>> {
+    let mut found = vec![];
+    if needle.is_empty() {
+        return found;
+    }
+
+    let mut start = 0;
+    while start < text.len() {
+        match match_at(&text[start..], needle) {
+            Some(len) => {
+                found.push(start..start + len);
+                start += len;
+            }
+            None => start += text[start..].chars().next().map_or(1, char::len_utf8),
+        }
+    }
+
+    found
+}
+
+// The length of the match of `needle` at the start of `text`
+fn match_at(text: &str, needle: &str) -> Option<usize> {
+    let mut chars = text.char_indices();
+
+    for wanted in needle.chars() {
+        let (_, c) = chars.next()?;
+        if !c.to_lowercase().eq(wanted.to_lowercase()) {
+            return None;
+        }
+    }
+
+    Some(chars.next().map_or(text.len(), |(i, _)| i))
+}
+
+//
+// Test Cases
+#[test]
+fn test_find() {
+    assert_eq!(
+        find("Hello hello HELLO", "hello"),
+        vec![0..5, 6..11, 12..17]
+    );
+    assert_eq!(find("Grüße, GRÜSSE", "grüße"), vec![0..7]);
+    assert_eq!(find("abc", "abcd"), vec![]);
+    assert_eq!(find("abc", ""), vec![]);
+}
+
+#[test]
+fn test_filter_matches() {
+    let clock = Clock::default();
+    let mut message = Message::new("Alice".to_owned(), "Lunch at noon?".to_owned());
+    message.mentions = vec!["bob".to_owned()];
+
+    let filter = |text: &str, author: &str, only_mentions| Filter {
+        text: text.to_owned(),
+        author: author.to_owned(),
+        only_mentions,
+        ..Default::default()
+    };
+
+    assert!(filter("", "", false).matches(&message, "bob", &clock));
+    assert!(filter("lunch", "ali", false).matches(&message, "bob", &clock));
+    assert!(!filter("dinner", "", false).matches(&message, "bob", &clock));
+    assert!(!filter("", "lice", false).matches(&message, "bob", &clock));
+    assert!(filter("", "", true).matches(&message, "bob", &clock));
+    assert!(!filter("", "", true).matches(&message, "carol", &clock));
+
+    let mut by_date = Filter {
+        date: "2023-04-01".to_owned(),
+        ..Default::default()
+    };
+    assert!(by_date.date().is_ok());
+    by_date.date = "April".to_owned();
+    assert!(by_date.date().is_err());
+}
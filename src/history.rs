@@ -0,0 +1,252 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use chrono::{NaiveDate, NaiveDateTime};
+
+use crate::database::{self, message::to_messages};
+use crate::message::{Message, DEFAULT_CHANNEL};
+
+const IRC_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+
+/// The file formats history can be exported to and imported from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// One JSON object per line, the same format as the retention archive
+    Jsonl,
+    /// A header row followed by one message per row
+    Csv,
+    /// Plain text lines: [time] #channel <user> content
+    Irc,
+}
+
+/// Which messages to export. Dates are in UTC, `until` is exclusive.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub channel: Option<String>,
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+}
+
+impl Filter {
+    fn range_ms(&self) -> (i64, i64) {
+        let to_ms = |date: NaiveDate| date.and_hms_opt(0, 0, 0).unwrap().timestamp_millis();
+
+        (
+            self.since.map(to_ms).unwrap_or(i64::MIN),
+            self.until.map(to_ms).unwrap_or(i64::MAX),
+        )
+    }
+}
+
+/// Writes the messages matching the filter to a file.
+/// Returns the number of exported messages.
+pub fn export(path: &Path, format: Format, filter: &Filter) -> io::Result<usize> {
+    let (since, until) = filter.range_ms();
+
+    let messages: Vec<Message> = to_messages(database::message::select_between(since, until))
+        .into_iter()
+        .filter(|message| match &filter.channel {
+            Some(channel) => &message.channel == channel,
+            None => true,
+        })
+        .collect();
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_messages(&mut writer, format, &messages)?;
+    writer.flush()?;
+
+    Ok(messages.len())
+}
+
+/// Reads messages from a file into the database, keeping their original
+/// timestamps. Messages that are already there are skipped, so importing
+/// a file twice does nothing the second time. Returns the number of
+/// imported messages.
+pub fn import(path: &Path, format: Format) -> io::Result<usize> {
+    let reader = BufReader::new(File::open(path)?);
+    let messages = read_messages(reader, format)?;
+
+    let imported = messages
+        .into_iter()
+        .filter(|message| database::message::import_message(message.clone()))
+        .count();
+
+    Ok(imported)
+}
+
+/// Serializes messages in the given format.
+pub fn write_messages<W: Write>(
+    writer: &mut W,
+    format: Format,
+    messages: &[Message],
+) -> io::Result<()> {
+    match format {
+        Format::Jsonl => {
+            for message in messages {
+                serde_json::to_writer(&mut *writer, message)?;
+                writeln!(writer)?;
+            }
+        }
+        Format::Csv => {
+            let mut csv = csv::Writer::from_writer(writer);
+            for message in messages {
+                csv.serialize(message)?;
+            }
+            csv.flush()?;
+        }
+        Format::Irc => {
+            for message in messages {
+                let Some(time) = NaiveDateTime::from_timestamp_millis(message.timestamp_ms) else {
+                    let error = format!("Invalid timestamp: {}", message.timestamp_ms);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+                };
+                writeln!(
+                    writer,
+                    "[{}] #{} <{}> {}",
+                    time.format(IRC_TIME_FORMAT),
+                    message.channel,
+                    message.username,
+                    escape_line(&message.content)
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Deserializes messages in the given format.
+pub fn read_messages<R: BufRead>(reader: R, format: Format) -> io::Result<Vec<Message>> {
+    match format {
+        Format::Jsonl => reader
+            .lines()
+            .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+            .map(|line| Ok(serde_json::from_str(&line?)?))
+            .collect(),
+        Format::Csv => csv::Reader::from_reader(reader)
+            .deserialize()
+            .map(|row| row.map_err(io::Error::from))
+            .collect(),
+        Format::Irc => reader
+            .lines()
+            .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+            .map(|line| parse_irc_line(&line?))
+            .collect(),
+    }
+}
+
+// Parses "[time] #channel <user> content"
+fn parse_irc_line(line: &str) -> io::Result<Message> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Invalid line: {line}"));
+
+    let rest = line.strip_prefix('[').ok_or_else(invalid)?;
+    let (time, rest) = rest.split_once("] ").ok_or_else(invalid)?;
+
+    // Logs without a channel go to the default one
+    let (channel, rest) = match rest.strip_prefix('#') {
+        Some(rest) => rest.split_once(' ').ok_or_else(invalid)?,
+        None => (DEFAULT_CHANNEL, rest),
+    };
+
+    // The name ends at "> ", so names and content may contain >
+    let rest = rest.strip_prefix('<').ok_or_else(invalid)?;
+    let (username, content) = match rest.split_once("> ") {
+        Some(split) => split,
+        None => (rest.strip_suffix('>').ok_or_else(invalid)?, ""),
+    };
+
+    let time = NaiveDateTime::parse_from_str(time, IRC_TIME_FORMAT)
+        .or_else(|_| NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S"))
+        .map_err(|_| invalid())?;
+
+    let mut message = Message::new(username.to_owned(), unescape_line(content.trim_start()));
+    message.channel = channel.to_owned();
+    message.timestamp_ms = time.timestamp_millis();
+
+    Ok(message)
+}
+
+// Keeps multi-line messages on a single log line
+fn escape_line(content: &str) -> String {
+    content.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape_line(content: &str) -> String {
+    let mut result = String::with_capacity(content.len());
+    let mut chars = content.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                Some(other) => result.push(other),
+                None => result.push('\\'),
+            }
+        } else {
+            result.push(c);
+        }
+    }
+
+    result
+}
+
+//
+// Test Cases
+#[cfg(test)]
+fn sample_messages() -> Vec<Message> {
+    let mut first = Message::new("alice".to_string(), "hello, \"world\"".to_string());
+    first.timestamp_ms = 1_680_350_400_123;
+
+    let mut second = Message::new("bob".to_string(), "two\nlines \\ here".to_string());
+    second.timestamp_ms = 1_680_350_460_000;
+    second.channel = "random".to_string();
+
+    vec![first, second]
+}
+
+#[test]
+fn test_round_trip_all_formats() {
+    for format in [Format::Jsonl, Format::Csv, Format::Irc] {
+        let mut buffer = vec![];
+        write_messages(&mut buffer, format, &sample_messages()).unwrap();
+
+        let messages = read_messages(buffer.as_slice(), format).unwrap();
+
+        assert_eq!(messages.len(), 2, "{:?}", format);
+        for (read, original) in messages.iter().zip(sample_messages()) {
+            assert_eq!(read.username, original.username, "{:?}", format);
+            assert_eq!(read.content, original.content, "{:?}", format);
+            assert_eq!(read.channel, original.channel, "{:?}", format);
+            assert_eq!(read.timestamp_ms, original.timestamp_ms, "{:?}", format);
+        }
+    }
+}
+
+#[test]
+fn test_parse_irc_line_without_channel() {
+    let message = parse_irc_line("[2023-04-01 12:00:00] <carol> hi there").unwrap();
+
+    assert_eq!(message.channel, DEFAULT_CHANNEL);
+    assert_eq!(message.username, "carol");
+    assert_eq!(message.content, "hi there");
+}
+
+#[test]
+fn test_parse_irc_line_with_brackets() {
+    let message = parse_irc_line("[2023-04-01 12:00:00] #dev <x>y> a > b").unwrap();
+    assert_eq!(message.username, "x>y");
+    assert_eq!(message.content, "a > b");
+
+    let message = parse_irc_line("[2023-04-01 12:00:00] <carol>").unwrap();
+    assert_eq!(message.content, "");
+}
+
+#[test]
+fn test_export_invalid_timestamp() {
+    let mut message = Message::new("alice".to_string(), "hi".to_string());
+    message.timestamp_ms = i64::MAX;
+
+    let error = write_messages(&mut vec![], Format::Irc, &[message]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
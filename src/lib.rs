@@ -0,0 +1,37 @@
+//! Chatter is a small chat server and client.
+//!
+//! The library holds the protocol (`Message`, `User`, `Request`, `Response`
+//! and the framing in `network`) and `ChatClient`, an async client other
+//! tools can use to talk to a server. The `rust_chatter` binary is built on it.
+
+pub mod args;
+pub mod backup;
+pub mod bot;
+pub mod chat_client;
+pub mod client;
+pub mod command;
+pub mod database;
+pub mod filter;
+pub mod history;
+pub mod markdown;
+pub mod message;
+pub mod moderation;
+pub mod network;
+pub mod notification;
+pub mod plugin;
+pub mod render;
+pub mod request;
+pub mod response;
+pub mod retention;
+pub mod server;
+pub mod server_commands;
+pub mod settings;
+pub mod theme;
+pub mod timestamp;
+pub mod typing;
+
+pub use args::Args;
+pub use chat_client::ChatClient;
+pub use message::{Message, User};
+pub use request::Request;
+pub use response::Response;
@@ -1,42 +1,55 @@
 use clap::Parser;
 
+use std::io::BufRead;
+use std::path::Path;
 use std::sync::Arc;
 
-mod client;
-mod database;
-mod message;
-mod network;
-mod request;
-mod response;
-mod server;
+use rust_chatter::args::{Args, BotKind, Command};
+use rust_chatter::{backup, bot, client, database, history, moderation, server};
 
 // ./target/debug/rust_chatter -s
 // ./target/debug/rust_chatter -u username
-
-/// Args is a struct that contains the command line arguments.
-#[derive(Debug, Parser)]
-#[command(name = "Chatter")]
-pub struct Args {
-    #[arg(short = 's', long = "server")]
-    is_server: bool,
-
-    #[arg(short, long, required = false, default_value = "127.0.0.1")]
-    address: String,
-
-    #[arg(short, long, required = false, default_value = "23432")]
-    port: String,
-
-    #[arg(short = 'u', long, required = false, default_value = "unknown")]
-    username: String,
-}
+// ./target/debug/rust_chatter export history.jsonl --channel general
+// ./target/debug/rust_chatter import history.jsonl
+// ./target/debug/rust_chatter backup chatter-backup.sqlite
+// ./target/debug/rust_chatter restore chatter-backup.sqlite
+// ./target/debug/rust_chatter set-role username admin
+// ./target/debug/rust_chatter -u echo bot echo
+// make 2>&1 | ./target/debug/rust_chatter -u ci bot notify --channel builds
 
 /// The main function is the entry point for the program.
 fn main() {
     let args: Arc<Args> = Arc::new(Args::parse());
     println!("{:?}", args);
 
+    if let Err(e) = args.validate() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
     // spawn(async { eframe::run_native(Box::new(app), native_options) });
 
+    let database = args.database.clone().unwrap_or_else(database::default_path);
+    if let Err(e) = database::set_path(&database) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
+    if let Some(Command::Bot {
+        kind,
+        channel,
+        token,
+    }) = &args.command
+    {
+        run_bot(&args, *kind, channel, token);
+        return;
+    }
+
+    if let Some(command) = &args.command {
+        run_command(command, &database);
+        return;
+    }
+
     // We only run a server or client. Client by default.
     if args.is_server {
         server::setup_server(args);
@@ -44,3 +57,90 @@ fn main() {
         client::client(args);
     }
 }
+
+/// run_command() runs a subcommand and exits the process on failure.
+fn run_command(command: &Command, database: &Path) {
+    let result = match command {
+        Command::Export {
+            output,
+            format,
+            channel,
+            since,
+            until,
+        } => {
+            let filter = history::Filter {
+                channel: channel.clone(),
+                since: *since,
+                until: *until,
+            };
+            history::export(output, *format, &filter)
+                .map(|count| println!("Exported {} messages to {}", count, output.display()))
+                .map_err(|e| e.to_string())
+        }
+        Command::Import { input, format } => history::import(input, *format)
+            .map(|count| println!("Imported {} messages from {}", count, input.display()))
+            .map_err(|e| e.to_string()),
+        Command::Backup { output } => backup::backup(output)
+            .map(|_| println!("Backed up {} to {}", database.display(), output.display())),
+        Command::Restore { input } => backup::restore(input, database).map(|previous| {
+            println!("Restored {} from {}", database.display(), input.display());
+            println!("The previous database was kept at {}", previous.display());
+        }),
+        Command::SetRole { username, role } => match database::user::get(username) {
+            Some(_) => {
+                database::user::set_role(username, *role);
+                moderation::log(moderation::Action::SetRole(*role), "console", username, "");
+                println!("{} is now {}", username, role);
+                Ok(())
+            }
+            None => Err(format!("No user named {}", username)),
+        },
+        Command::Bot { .. } => unreachable!("Bots are run by run_bot"),
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// run_bot() connects one of the built-in bots and runs it until it stops.
+fn run_bot(args: &Args, kind: BotKind, channel: &str, token: &str) {
+    let address = format!("{}:{}", args.address, args.port);
+    let interval = std::time::Duration::from_secs(1);
+
+    let client = match bot::BotClient::connect(&address, &args.username, token) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // The bot needs the token to log in as the same user again
+    if token.is_empty() {
+        eprintln!(
+            "Logged in as {}, reconnect with --token {}",
+            args.username,
+            client.token()
+        );
+    }
+
+    let result = match kind {
+        BotKind::Echo => client.run(&mut bot::Echo, interval),
+        BotKind::Notify => {
+            let mut notify = bot::Notify {
+                channel: channel.to_owned(),
+                lines: std::io::stdin().lock().lines(),
+            };
+            client.run(&mut notify, interval)
+        }
+    };
+
+    client.disconnect();
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
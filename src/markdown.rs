@@ -0,0 +1,317 @@
+/// Block is a part of a message that is laid out on its own lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Block {
+    Line(Vec<Span>),
+    Quote(Vec<Span>), // A line starting with >
+    Code { language: String, code: String },
+}
+
+/// Span is a run of text with a single style.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Span {
+    Text(String),
+    Bold(String),
+    Italic(String),
+    Code(String),
+    Link { text: String, url: String },
+}
+
+/// Parses the markdown we support: **bold**, *italic* or _italic_, `code`,
+/// ``` fenced code blocks ```, > quotes, [links](url) and bare urls.
+/// Anything that does not parse is shown as it was written.
+pub fn parse(content: &str) -> Vec<Block> {
+    let mut blocks = vec![];
+    let mut lines = content.lines();
+
+    while let Some(line) = lines.next() {
+        if let Some(language) = line.trim_start().strip_prefix("```") {
+            // Everything up to the closing fence, or the end of the message
+            let code: Vec<&str> = lines
+                .by_ref()
+                .take_while(|line| !line.trim_start().starts_with("```"))
+                .collect();
+
+            blocks.push(Block::Code {
+                language: language.trim().to_owned(),
+                code: code.join("\n"),
+            });
+        } else if let Some(quote) = line.strip_prefix('>') {
+            blocks.push(Block::Quote(parse_spans(quote.trim_start())));
+        } else {
+            blocks.push(Block::Line(parse_spans(line)));
+        }
+    }
+
+    blocks
+}
+
+/// Parses the inline styles of a single line.
+pub fn parse_spans(line: &str) -> Vec<Span> {
+    let mut spans = vec![];
+    let mut text = String::new();
+    let mut rest = line;
+
+    while let Some(c) = rest.chars().next() {
+        let previous = text.chars().last();
+
+        if let Some((span, after)) = parse_span(rest, previous) {
+            if !text.is_empty() {
+                spans.push(Span::Text(std::mem::take(&mut text)));
+            }
+            spans.push(span);
+            rest = after;
+        } else {
+            text.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    if !text.is_empty() {
+        spans.push(Span::Text(text));
+    }
+
+    spans
+}
+
+// Tries to parse a styled span at the start of `rest`,
+// returning it and what follows it.
+fn parse_span(rest: &str, previous: Option<char>) -> Option<(Span, &str)> {
+    if let Some(inner) = rest.strip_prefix('`') {
+        let (code, after) = inner.split_once('`')?;
+        return (!code.is_empty()).then(|| (Span::Code(code.to_owned()), after));
+    }
+
+    if let Some(inner) = rest.strip_prefix("**") {
+        let (bold, after) = inner.split_once("**")?;
+        return is_wrapped(bold).then(|| (Span::Bold(bold.to_owned()), after));
+    }
+
+    for marker in ['*', '_'] {
+        if let Some(inner) = rest.strip_prefix(marker) {
+            // snake_case and 2*3*4 are not italic
+            if previous.is_some_and(char::is_alphanumeric) {
+                return None;
+            }
+            let (italic, after) = inner.split_once(marker)?;
+            if after.starts_with(char::is_alphanumeric) || !is_wrapped(italic) {
+                return None;
+            }
+            return Some((Span::Italic(italic.to_owned()), after));
+        }
+    }
+
+    if let Some(inner) = rest.strip_prefix('[') {
+        let (text, after) = inner.split_once("](")?;
+        let (url, after) = after.split_once(')')?;
+        if !is_url(url) || text.is_empty() {
+            return None;
+        }
+        let link = Span::Link {
+            text: text.to_owned(),
+            url: url.to_owned(),
+        };
+        return Some((link, after));
+    }
+
+    if is_url(rest) {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        // Punctuation after a url is part of the sentence
+        let url = rest[..end].trim_end_matches(['.', ',', '!', '?', ')', ':', ';']);
+        let link = Span::Link {
+            text: url.to_owned(),
+            url: url.to_owned(),
+        };
+        return Some((link, &rest[url.len()..]));
+    }
+
+    None
+}
+
+// Styled text may not start or end with a space, e.g. "2 * 3 * 4"
+fn is_wrapped(text: &str) -> bool {
+    !text.is_empty() && text.trim() == text
+}
+
+fn is_url(text: &str) -> bool {
+    text.starts_with("https://") || text.starts_with("http://")
+}
+
+/// Token is how a piece of code is colored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    Plain,
+    Keyword,
+    String,
+    Number,
+    Comment,
+}
+
+// Keywords of the languages we share most, one list is good enough
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "class", "const", "continue", "crate", "def", "elif", "else",
+    "enum", "false", "fn", "for", "from", "function", "if", "impl", "import", "in", "let", "loop",
+    "match", "mod", "mut", "new", "None", "null", "pub", "return", "self", "Self", "static",
+    "struct", "trait", "true", "type", "use", "var", "where", "while",
+];
+
+/// Splits code into colored tokens. Languages that use # for comments
+/// are told apart by the fence's language, everything else uses // and /* */.
+pub fn highlight<'a>(code: &'a str, language: &str) -> Vec<(Token, &'a str)> {
+    let hash_comments = matches!(
+        language,
+        "py" | "python" | "sh" | "bash" | "toml" | "yaml" | "yml" | "rb" | "ruby"
+    );
+
+    let mut tokens: Vec<(Token, &str)> = vec![];
+    let mut rest = code;
+
+    while let Some(c) = rest.chars().next() {
+        let line_comment = rest.starts_with("//") || (hash_comments && c == '#');
+        let comment = line_comment || rest.starts_with("/*");
+
+        let end = if line_comment {
+            rest.find('\n').unwrap_or(rest.len())
+        } else if rest.starts_with("/*") {
+            rest.find("*/").map_or(rest.len(), |end| end + 2)
+        } else if c == '"' {
+            string_end(rest)
+        } else if c.is_alphanumeric() || c == '_' {
+            rest.find(|c: char| !c.is_alphanumeric() && c != '_')
+                .unwrap_or(rest.len())
+        } else {
+            c.len_utf8()
+        };
+
+        let (text, after) = rest.split_at(end);
+        let token = if comment {
+            Token::Comment
+        } else if c == '"' {
+            Token::String
+        } else if c.is_ascii_digit() {
+            Token::Number
+        } else if KEYWORDS.contains(&text) {
+            Token::Keyword
+        } else {
+            Token::Plain
+        };
+
+        // Runs of plain text are kept together
+        match tokens.last_mut() {
+            Some((Token::Plain, last)) if token == Token::Plain => {
+                let start = code.len() - rest.len() - last.len();
+                *last = &code[start..code.len() - after.len()];
+            }
+            _ => tokens.push((token, text)),
+        }
+
+        rest = after;
+    }
+
+    tokens
+}
+
+// The end of the string starting at the beginning of `code`, after its closing quote
+fn string_end(code: &str) -> usize {
+    let mut escaped = false;
+
+    for (i, c) in code.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return i + 1,
+            '\n' => return i,
+            _ => {}
+        }
+    }
+
+    code.len()
+}
+
+//
+// Test Cases
+#[test]
+fn test_parse_spans() {
+    let text = |s: &str| Span::Text(s.to_owned());
+
+    assert_eq!(
+        parse_spans("a **bold** and *italic* `code`"),
+        vec![
+            text("a "),
+            Span::Bold("bold".to_owned()),
+            text(" and "),
+            Span::Italic("italic".to_owned()),
+            text(" "),
+            Span::Code("code".to_owned()),
+        ]
+    );
+
+    // Not styles
+    assert_eq!(
+        parse_spans("snake_case_name"),
+        vec![text("snake_case_name")]
+    );
+    assert_eq!(parse_spans("2 * 3 * 4"), vec![text("2 * 3 * 4")]);
+    assert_eq!(parse_spans("a **b"), vec![text("a **b")]);
+
+    assert_eq!(
+        parse_spans("see https://example.com/a, or [docs](https://docs.rs)"),
+        vec![
+            text("see "),
+            Span::Link {
+                text: "https://example.com/a".to_owned(),
+                url: "https://example.com/a".to_owned(),
+            },
+            text(", or "),
+            Span::Link {
+                text: "docs".to_owned(),
+                url: "https://docs.rs".to_owned(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_parse_blocks() {
+    let blocks = parse("look:\n```rust\nfn main() {}\n```\n> quoted");
+
+    assert_eq!(blocks.len(), 3);
+    assert_eq!(
+        blocks[1],
+        Block::Code {
+            language: "rust".to_owned(),
+            code: "fn main() {}".to_owned(),
+        }
+    );
+    assert_eq!(
+        blocks[2],
+        Block::Quote(vec![Span::Text("quoted".to_owned())])
+    );
+}
+
+#[test]
+fn test_highlight() {
+    let tokens = highlight("let x = \"a\\\"b\"; // hi\n42", "rust");
+
+    assert_eq!(tokens[0], (Token::Keyword, "let"));
+    assert!(tokens.contains(&(Token::String, "\"a\\\"b\"")));
+    assert!(tokens.contains(&(Token::Comment, "// hi")));
+    assert_eq!(tokens.last(), Some(&(Token::Number, "42")));
+
+    // Nothing is lost
+    let joined: String = tokens.iter().map(|(_, text)| *text).collect();
+    assert_eq!(joined, "let x = \"a\\\"b\"; // hi\n42");
+}
+
+#[test]
+fn test_highlight_hash_comments() {
+    // Only some languages start comments with #
+    let tokens = highlight("#[derive(Debug)]", "rust");
+    assert!(tokens.iter().all(|(token, _)| *token != Token::Comment));
+
+    let tokens = highlight("#include <stdio.h> // io", "c");
+    assert_eq!(tokens[0], (Token::Plain, "#include <stdio.h> "));
+    assert_eq!(tokens.last(), Some(&(Token::Comment, "// io")));
+
+    let tokens = highlight("x = 1 # one", "python");
+    assert_eq!(tokens.last(), Some(&(Token::Comment, "# one")));
+}
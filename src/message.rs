@@ -4,12 +4,88 @@ use serde::{Deserialize, Serialize};
 
 use crate::database::MessageRow;
 
-#[derive(Serialize, Deserialize, Debug)]
+/// The channel messages are posted to when none is given.
+pub const DEFAULT_CHANNEL: &str = "general";
+
+/// The channel for direct messages between two users, e.g. @alice+bob
+pub fn direct_channel(a: &str, b: &str) -> String {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    format!("@{}+{}", first, second)
+}
+
+/// Returns true if the user may read the channel.
+/// Direct message channels are only readable by their two users.
+pub fn can_read(channel: &str, username: &str) -> bool {
+    match channel.strip_prefix('@') {
+        Some(users) => users.split('+').any(|user| user == username),
+        None => true,
+    }
+}
+
+/// Usernames may only contain letters, digits, `_`, `-` and `.`
+/// so they can be used in commands and channel names.
+pub fn is_valid_username(username: &str) -> bool {
+    !username.is_empty() && username.chars().all(is_username_char)
+}
+
+fn is_username_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.')
+}
+
+/// Finds the @usernames in a message, in order and without repeats.
+/// Punctuation after a name is not part of it, e.g. "@bob." is bob,
+/// and addresses like "bob@example.com" are not mentions.
+pub fn parse_mentions(content: &str) -> Vec<String> {
+    let mut mentions: Vec<String> = vec![];
+    let mut previous = ' ';
+
+    for (i, c) in content.char_indices() {
+        if c == '@' && !is_username_char(previous) {
+            let name: String = content[i + 1..]
+                .chars()
+                .take_while(|&c| is_username_char(c))
+                .collect();
+            let name = name.trim_end_matches(['.', '-']);
+
+            if !name.is_empty() && !mentions.iter().any(|m| m == name) {
+                mentions.push(name.to_owned());
+            }
+        }
+        previous = c;
+    }
+
+    mentions
+}
+
+// Serializes a list of names as one space separated string
+mod space_separated {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(names: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&names.join(" "))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<String>, D::Error> {
+        let names = String::deserialize(deserializer)?;
+        Ok(names.split_whitespace().map(str::to_owned).collect())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Message {
     pub id: i32, // Optional -- This gets automatically set by the database
     pub username: String,
     pub content: String,
     pub timestamp_ms: i64, // Optional -- This gets automatically set by the database
+    pub channel: String,
+    pub pinned: bool, // Pinned messages are never removed by the retention policy
+
+    // Usernames mentioned with @, set by the server. Written as one
+    // string so the message still fits in a CSV row.
+    #[serde(default, with = "space_separated")]
+    pub mentions: Vec<String>,
 }
 
 impl Message {
@@ -20,22 +96,26 @@ impl Message {
             username,
             content,
             timestamp_ms: 0,
+            channel: DEFAULT_CHANNEL.to_owned(),
+            pinned: false,
+            mentions: vec![],
         }
     }
 
-    // Get the current time from the timestamp
-    pub fn get_time(&self) -> String {
-        // TODO: Figure out how to get the local time
-        // from the timestamp
+    /// Returns true if the message mentions the user.
+    pub fn mentions(&self, username: &str) -> bool {
+        self.mentions.iter().any(|name| name == username)
+    }
 
-        // use chrono::{DateTime, FsixedOffset, Local, Utc};
-        // let now = chrono::Local::now();
-        // let naive_date = chrono::NaiveDateTime::from_timestamp(self.timestamp_ms / 1000, 0);
-        // let local = chrono::DateTime::<chrono::Local>::from_utc(naive_date, );
+    // Get the local time from the timestamp. The GUI uses a `timestamp::Clock` instead.
+    pub fn get_time(&self) -> String {
+        use chrono::TimeZone;
 
-        // let time = chrono::NaiveDateTime::from_timestamp(self.timestamp_ms / 1000, 0);
-        let time = chrono::NaiveDateTime::from_timestamp_opt(self.timestamp_ms / 1000, 0).unwrap();
-        time.to_string()
+        let time = chrono::Local
+            .timestamp_millis_opt(self.timestamp_ms)
+            .single()
+            .unwrap_or_default();
+        time.format("%Y-%m-%d %H:%M:%S").to_string()
     }
 
     // Convert to MessageRow
@@ -44,6 +124,9 @@ impl Message {
             username: Some(self.username.clone()),
             content: Some(self.content.clone()),
             timestamp_ms: Some(self.timestamp_ms),
+            channel: Some(self.channel.clone()),
+            pinned: Some(self.pinned),
+            mentions: Some(self.mentions.join(" ")),
             ..Default::default()
         }
     }
@@ -51,6 +134,11 @@ impl Message {
 
 impl fmt::Display for Message {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Written with /me
+        if let Some(action) = self.content.strip_prefix("/me ") {
+            return write!(f, "{} - * {} {}", self.get_time(), self.username, action);
+        }
+
         write!(
             f,
             "{} - {}: {}",
@@ -65,6 +153,7 @@ impl fmt::Display for Message {
 pub struct User {
     pub id: i32, // Optional -- This gets automatically set by the database
     pub username: String,
+    pub token: String, // Proves the username is ours when logging in, never sent back in lists
 }
 
 impl fmt::Display for User {
@@ -72,3 +161,170 @@ impl fmt::Display for User {
         write!(f, "{}", self.username)
     }
 }
+
+/// Profile is the public information a user shares about themselves.
+/// Empty fields have not been set.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Profile {
+    pub username: String,
+    pub display_name: String,
+    pub status: String,
+    pub avatar: String,            // A link to an image
+    pub timezone: String,          // e.g. Europe/Berlin or +02:00
+    pub role: Role,                // Read only, changed through moderation
+    pub share_read_receipts: bool, // Whether others see which messages we have read
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            username: String::new(),
+            display_name: String::new(),
+            status: String::new(),
+            avatar: String::new(),
+            timezone: String::new(),
+            role: Role::default(),
+            share_read_receipts: true,
+        }
+    }
+}
+
+impl Profile {
+    /// The name to show for this user
+    pub fn name(&self) -> &str {
+        if self.display_name.is_empty() {
+            &self.username
+        } else {
+            &self.display_name
+        }
+    }
+}
+
+/// Channel is a place messages are posted to. Channels are created
+/// by posting to them.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Channel {
+    pub name: String,
+    pub topic: String,
+}
+
+/// Role decides what a user is allowed to do on the server.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Role {
+    Admin,
+    Moderator,
+    #[default]
+    Member,
+    Muted,
+}
+
+impl Role {
+    /// Higher ranks can moderate lower ones
+    fn rank(self) -> u8 {
+        match self {
+            Role::Admin => 3,
+            Role::Moderator => 2,
+            Role::Member | Role::Muted => 1,
+        }
+    }
+
+    pub fn can_moderate(self) -> bool {
+        self.rank() >= Role::Moderator.rank()
+    }
+
+    pub fn can_post(self) -> bool {
+        self != Role::Muted
+    }
+
+    /// Returns true if a user with this role may moderate a user with the other role
+    pub fn outranks(self, other: Role) -> bool {
+        self.can_moderate() && self.rank() > other.rank()
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Role::Admin => "admin",
+            Role::Moderator => "moderator",
+            Role::Member => "member",
+            Role::Muted => "muted",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "admin" => Ok(Role::Admin),
+            "moderator" => Ok(Role::Moderator),
+            "member" => Ok(Role::Member),
+            "muted" => Ok(Role::Muted),
+            _ => Err(format!("Unknown role {}", s)),
+        }
+    }
+}
+
+/// Ban keeps a user, and the address they were connected from, off the server.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Ban {
+    pub username: String,
+    pub address: String, // Empty if the user was not connected
+    pub reason: String,
+    pub banned_by: String,
+    pub timestamp_ms: i64,
+}
+
+/// Unread is how far a user has read in a channel.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Unread {
+    pub channel: String,
+    pub last_read: i32, // Id of the last message seen
+    pub count: u32,     // Messages by others after it
+}
+
+/// ServerInfo describes the server, for the About window.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ServerInfo {
+    pub version: String, // Of the server program
+    pub protocol: u32,
+    pub uptime_secs: u64,
+    pub motd: String, // Message of the day, may be empty
+}
+
+/// AuditEntry records a single moderation action.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditEntry {
+    pub action: String,
+    pub actor: String,
+    pub target: String,
+    pub reason: String,
+    pub timestamp_ms: i64,
+}
+
+//
+// Test Cases
+#[test]
+fn test_direct_channel() {
+    let channel = direct_channel("bob", "alice");
+
+    assert_eq!(channel, "@alice+bob");
+    assert_eq!(channel, direct_channel("alice", "bob"));
+    assert!(can_read(&channel, "bob"));
+    assert!(!can_read(&channel, "carol"));
+    assert!(can_read(DEFAULT_CHANNEL, "carol"));
+}
+
+#[test]
+fn test_parse_mentions() {
+    assert_eq!(
+        parse_mentions("@alice and @bob.smith, ask @alice."),
+        vec!["alice", "bob.smith"]
+    );
+    assert!(parse_mentions("mail bob@example.com").is_empty());
+    assert!(parse_mentions("just an @ sign").is_empty());
+    assert_eq!(parse_mentions("(@carol)"), vec!["carol"]);
+}
@@ -0,0 +1,128 @@
+use std::fmt;
+
+use crate::database;
+use crate::message::{AuditEntry, Ban, Role};
+use crate::request::Session;
+use crate::response::Response;
+
+/// Action is a moderation action, as written to the audit log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Kick,
+    Ban,
+    Unban,
+    Mute,
+    Unmute,
+    SetRole(Role),
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Kick => write!(f, "kick"),
+            Action::Ban => write!(f, "ban"),
+            Action::Unban => write!(f, "unban"),
+            Action::Mute => write!(f, "mute"),
+            Action::Unmute => write!(f, "unmute"),
+            Action::SetRole(role) => write!(f, "role {}", role),
+        }
+    }
+}
+
+/// moderate() checks that the session may apply the action to the target,
+/// applies it and records it in the audit log.
+pub fn moderate(action: Action, target: String, reason: String, session: &Session) -> Response {
+    let Some(actor) = session.username.clone() else {
+        return Response::Error("You must log in first".to_owned());
+    };
+
+    if target.is_empty() {
+        return Response::Error(format!("Who do you want to {}?", action));
+    }
+
+    let actor_role = database::user::role(&actor);
+    let target_role = database::user::role(&target);
+
+    if let Err(e) = check(action, actor_role, target_role) {
+        return Response::Error(e);
+    }
+
+    if database::user::get(&target).is_none() {
+        return Response::Error(format!("No user named {}", target));
+    }
+
+    match action {
+        Action::Kick => {
+            if !session.state.disconnect(&target) {
+                return Response::Error(format!("{} is not connected", target));
+            }
+        }
+        Action::Ban => {
+            database::moderation::add_ban(Ban {
+                username: target.clone(),
+                address: session.state.address(&target).unwrap_or_default(),
+                reason: reason.clone(),
+                banned_by: actor.clone(),
+                timestamp_ms: database::current_timestamp(),
+            });
+            session.state.disconnect(&target);
+        }
+        Action::Unban => database::moderation::remove_ban(&target),
+        Action::Mute => database::user::set_role(&target, Role::Muted),
+        Action::Unmute => database::user::set_role(&target, Role::Member),
+        Action::SetRole(role) => database::user::set_role(&target, role),
+    }
+
+    log(action, &actor, &target, &reason);
+
+    Response::OK
+}
+
+/// Records an action in the audit log.
+pub fn log(action: Action, actor: &str, target: &str, reason: &str) {
+    database::moderation::add_audit(AuditEntry {
+        action: action.to_string(),
+        actor: actor.to_owned(),
+        target: target.to_owned(),
+        reason: reason.to_owned(),
+        timestamp_ms: database::current_timestamp(),
+    });
+}
+
+// Moderators can act on members, admins can also act on moderators
+// and are the only ones who can hand out roles.
+fn check(action: Action, actor: Role, target: Role) -> Result<(), String> {
+    if let Action::SetRole(_) = action {
+        if actor != Role::Admin {
+            return Err("Only admins can change roles".to_owned());
+        }
+        return Ok(());
+    }
+
+    if !actor.can_moderate() {
+        return Err("You are not a moderator".to_owned());
+    }
+
+    if !actor.outranks(target) {
+        return Err(format!("You cannot {} a {}", action, target));
+    }
+
+    Ok(())
+}
+
+//
+// Test Cases
+#[test]
+fn test_check_permissions() {
+    assert!(check(Action::Kick, Role::Moderator, Role::Member).is_ok());
+    assert!(check(Action::Unmute, Role::Moderator, Role::Muted).is_ok());
+    assert!(check(Action::Ban, Role::Admin, Role::Moderator).is_ok());
+
+    assert!(check(Action::Kick, Role::Member, Role::Member).is_err());
+    assert!(check(Action::Mute, Role::Moderator, Role::Moderator).is_err());
+    assert!(check(Action::Ban, Role::Moderator, Role::Admin).is_err());
+    assert!(check(Action::Kick, Role::Admin, Role::Admin).is_err());
+
+    assert!(check(Action::SetRole(Role::Admin), Role::Moderator, Role::Member).is_err());
+    assert!(check(Action::SetRole(Role::Moderator), Role::Admin, Role::Member).is_ok());
+}
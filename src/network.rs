@@ -1,8 +1,58 @@
+use std::io::ErrorKind;
 use std::io::Read;
 use std::io::Write;
 
 use std::net::TcpStream;
 use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The version of the protocol, raised when requests or responses
+/// change in a way older clients or servers cannot read.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Heartbeat is how often an idle client pings the server, and how long
+/// either side waits for the other before it gives up on the connection.
+/// A server that hears nothing for `timeout` pings the client, and hangs
+/// up if no Pong arrives within another `timeout`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Heartbeat {
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+// Every value is sent as its bincode size, a little endian u64,
+// followed by the bincode bytes.
+
+/// frame() serializes a value the way it is sent over the connection.
+pub fn frame<T>(value: &T) -> Vec<u8>
+where
+    T: serde::Serialize,
+{
+    let bytes = bincode::serialize(value).unwrap();
+
+    let mut framed = bincode::serialize(&bytes.len()).unwrap();
+    framed.extend(bytes);
+    framed
+}
+
+/// The number of bytes `frame(value)` takes, without building it.
+pub fn frame_len<T>(value: &T) -> u64
+where
+    T: serde::Serialize,
+{
+    8 + bincode::serialized_size(value).unwrap_or(0)
+}
 
 /// Write then Read
 pub fn send_get<T, U>(value: T, conn: Arc<TcpStream>) -> U
@@ -14,22 +64,68 @@ where
     get(conn).unwrap()
 }
 
+/// Write then Read, returning None if the connection was lost
+pub fn request<T, U>(value: T, conn: Arc<TcpStream>) -> Option<U>
+where
+    T: serde::Serialize,
+    U: serde::de::DeserializeOwned,
+{
+    send(value, conn.clone());
+    get(conn)
+}
+
 /// write_from_connection
 pub fn send<T>(value: T, conn: Arc<TcpStream>)
 where
     T: serde::Serialize,
 {
-    let bytes = bincode::serialize(&value).unwrap();
-
-    // Get the size of the message
-    let byte_size = bincode::serialize(&bytes.len()).unwrap();
+    let bytes = frame(&value);
 
     if let Ok(mut conn_locked) = conn.try_clone() {
-        // Send the size of the message
-        if conn_locked.write_all(&byte_size).is_ok() {
-            // Then send the message
-            conn_locked.write_all(&bytes).unwrap();
+        // Send the size and the message in one go
+        let _ = conn_locked.write_all(&bytes);
+    }
+}
+
+/// Received is what a read on a connection with a read timeout got.
+pub enum Received<T> {
+    Value(T),
+    Idle,   // Nothing arrived before the timeout
+    Closed, // The connection was lost, or a message only arrived in part
+}
+
+/// receive() is get() for connections with a read timeout. Timing out
+/// before the first byte is Idle, so the caller can decide what to do.
+pub fn receive<T>(conn: &TcpStream) -> Received<T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let mut conn = conn;
+    let mut byte_size = [0u8; 8];
+
+    match conn.read(&mut byte_size[..1]) {
+        Ok(0) => return Received::Closed,
+        Ok(_) => (),
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+            return Received::Idle
         }
+        Err(_) => return Received::Closed,
+    }
+
+    // The rest of the message follows, a timeout now is a lost connection
+    if conn.read_exact(&mut byte_size[1..]).is_err() {
+        return Received::Closed;
+    }
+    let size = u64::from_le_bytes(byte_size);
+
+    let mut buffer = vec![0; size as usize];
+    if conn.read_exact(&mut buffer).is_err() {
+        return Received::Closed;
+    }
+
+    match bincode::deserialize(&buffer) {
+        Ok(value) => Received::Value(value),
+        Err(_) => Received::Closed,
     }
 }
 
@@ -49,13 +145,56 @@ where
         // Using the size, allocate buffer of that size
         let mut buffer = vec![0; size as usize];
 
-        // Then read the message into the buffer
-        conn_locked
-            .read_exact(&mut buffer)
-            .expect("Reading Bytes into buffer failed");
+        // Then read the message into the buffer. It can time out
+        // halfway when the other side stops responding.
+        conn_locked.read_exact(&mut buffer).ok()?;
 
         let request: T = bincode::deserialize(&buffer).unwrap();
         return Some(request);
     }
     None
 }
+
+/// Async version of send(), for tokio connections.
+pub async fn send_async<T, W>(value: T, conn: &mut W) -> std::io::Result<()>
+where
+    T: serde::Serialize,
+    W: AsyncWrite + Unpin,
+{
+    conn.write_all(&frame(&value)).await?;
+    conn.flush().await
+}
+
+/// Async version of get(). It returns None if the connection was lost
+/// or the message could not be read.
+pub async fn get_async<T, R>(conn: &mut R) -> Option<T>
+where
+    T: serde::de::DeserializeOwned,
+    R: AsyncRead + Unpin,
+{
+    let mut byte_size = [0u8; 8];
+    conn.read_exact(&mut byte_size).await.ok()?;
+
+    let size: u64 = bincode::deserialize(&byte_size).ok()?;
+
+    let mut buffer = vec![0; size as usize];
+    conn.read_exact(&mut buffer).await.ok()?;
+
+    bincode::deserialize(&buffer).ok()
+}
+
+//
+// Test Cases
+#[test]
+fn test_frame_round_trip() {
+    let framed = frame(&"hello".to_string());
+    assert_eq!(framed[..8], (framed.len() as u64 - 8).to_le_bytes());
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let value: Option<String> = runtime.block_on(get_async(&mut framed.as_slice()));
+    assert_eq!(value.as_deref(), Some("hello"));
+
+    // A truncated frame is a lost connection
+    let value: Option<String> = runtime.block_on(get_async(&mut &framed[..10]));
+    assert_eq!(value, None);
+}
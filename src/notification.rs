@@ -0,0 +1,23 @@
+/// Shows a desktop notification, playing the system's message sound if asked to.
+/// It is sent from another thread since talking to the notification daemon can
+/// take a moment.
+pub fn show(summary: String, body: String, sound: bool) {
+    std::thread::spawn(move || {
+        let mut notification = notify_rust::Notification::new();
+        notification
+            .appname("Chatter")
+            .summary(&summary)
+            .body(&body);
+
+        if sound {
+            notification.sound_name("message-new-instant");
+        }
+
+        let result = notification.show();
+
+        // e.g. there is no notification daemon running
+        if let Err(e) = result {
+            eprintln!("Could not show a notification: {}", e);
+        }
+    });
+}
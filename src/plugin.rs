@@ -0,0 +1,158 @@
+use crate::message::{Message, DEFAULT_CHANNEL};
+
+/// Hook is what a plugin decides to do with the traffic it is shown.
+#[derive(Debug)]
+pub enum Hook {
+    /// Let it through, possibly after changing it
+    Continue,
+    /// Stop it, the error is sent to the client
+    Reject(String),
+    /// Let it through and post these messages after it
+    Reply(Vec<Message>),
+}
+
+/// Plugin hooks into the server's request handling. Every method has a
+/// default that lets traffic through, so plugins only implement what they need.
+pub trait Plugin: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Called before a message is stored. `message.username` is the sender.
+    fn on_message(&self, _message: &mut Message) -> Hook {
+        Hook::Continue
+    }
+
+    /// Called when a user logs in. Rejecting refuses the login.
+    fn on_join(&self, _username: &str) -> Hook {
+        Hook::Continue
+    }
+
+    /// Called when a user logs out or disconnects. Rejecting does nothing.
+    fn on_leave(&self, _username: &str) -> Hook {
+        Hook::Continue
+    }
+}
+
+/// Plugins runs hooks in the order the plugins were added.
+#[derive(Default)]
+pub struct Plugins {
+    plugins: Vec<Box<dyn Plugin>>,
+}
+
+impl std::fmt::Debug for Plugins {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let names: Vec<&str> = self.plugins.iter().map(|p| p.name()).collect();
+        f.debug_list().entries(names).finish()
+    }
+}
+
+impl Plugins {
+    pub fn add(&mut self, plugin: Box<dyn Plugin>) {
+        self.plugins.push(plugin);
+    }
+
+    /// Returns the replies to post after the message, or why it was rejected.
+    pub fn on_message(&self, message: &mut Message) -> Result<Vec<Message>, String> {
+        self.run(|plugin| plugin.on_message(message))
+    }
+
+    pub fn on_join(&self, username: &str) -> Result<Vec<Message>, String> {
+        self.run(|plugin| plugin.on_join(username))
+    }
+
+    pub fn on_leave(&self, username: &str) -> Vec<Message> {
+        self.run(|plugin| plugin.on_leave(username))
+            .unwrap_or_default()
+    }
+
+    // The first rejection stops the remaining plugins
+    fn run(&self, mut hook: impl FnMut(&dyn Plugin) -> Hook) -> Result<Vec<Message>, String> {
+        let mut replies = vec![];
+
+        for plugin in &self.plugins {
+            match hook(plugin.as_ref()) {
+                Hook::Continue => {}
+                Hook::Reject(e) => return Err(e),
+                Hook::Reply(messages) => replies.extend(messages),
+            }
+        }
+
+        Ok(replies)
+    }
+}
+
+/// Announcer posts a notice to the default channel when users come and go.
+pub struct Announcer;
+
+impl Announcer {
+    fn notice(text: String) -> Hook {
+        let mut message = Message::new("server".to_owned(), text);
+        message.channel = DEFAULT_CHANNEL.to_owned();
+        Hook::Reply(vec![message])
+    }
+}
+
+impl Plugin for Announcer {
+    fn name(&self) -> &str {
+        "announcer"
+    }
+
+    fn on_join(&self, username: &str) -> Hook {
+        Announcer::notice(format!("/me welcomes {} to the server", username))
+    }
+
+    fn on_leave(&self, username: &str) -> Hook {
+        Announcer::notice(format!("/me waves goodbye to {}", username))
+    }
+}
+
+//
+// Test Cases
+#[cfg(test)]
+struct Shout;
+
+#[cfg(test)]
+impl Plugin for Shout {
+    fn name(&self) -> &str {
+        "shout"
+    }
+
+    fn on_message(&self, message: &mut Message) -> Hook {
+        message.content = message.content.to_uppercase();
+        Hook::Continue
+    }
+}
+
+#[cfg(test)]
+struct NoSpam;
+
+#[cfg(test)]
+impl Plugin for NoSpam {
+    fn name(&self) -> &str {
+        "no_spam"
+    }
+
+    fn on_message(&self, message: &mut Message) -> Hook {
+        if message.content.contains("SPAM") {
+            return Hook::Reject("No spam please".to_owned());
+        }
+        Hook::Reply(vec![Message::new("bot".to_owned(), "seen".to_owned())])
+    }
+}
+
+#[test]
+fn test_plugins_run_in_order() {
+    let mut plugins = Plugins::default();
+    plugins.add(Box::new(Shout));
+    plugins.add(Box::new(NoSpam));
+
+    let mut message = Message::new("bob".to_owned(), "hello".to_owned());
+    let replies = plugins.on_message(&mut message).unwrap();
+    assert_eq!(message.content, "HELLO");
+    assert_eq!(replies.len(), 1);
+
+    // Shout turns "spam" into "SPAM" before NoSpam sees it
+    let mut message = Message::new("bob".to_owned(), "buy spam".to_owned());
+    assert!(plugins.on_message(&mut message).is_err());
+
+    assert!(plugins.on_join("bob").unwrap().is_empty());
+}
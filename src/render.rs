@@ -0,0 +1,240 @@
+use eframe::egui;
+use egui::text::{LayoutJob, TextFormat};
+use egui::{Color32, FontId, RichText, Sense, Ui};
+
+use crate::database;
+use crate::filter;
+use crate::markdown::{self, Block, Span, Token};
+use crate::message::Message;
+use crate::timestamp::Clock;
+
+// Messages closer together than this are grouped under one header
+const GROUP_GAP_MS: i64 = 5 * 60 * 1000;
+
+/// Whether `message` continues the group of `previous`, so it is drawn without a header.
+pub fn continues(previous: &Message, message: &Message) -> bool {
+    previous.username == message.username
+        && message.timestamp_ms - previous.timestamp_ms < GROUP_GAP_MS
+        && !previous.content.starts_with("/me ")
+        && !message.content.starts_with("/me ")
+}
+
+/// Draws a message, with its content rendered as markdown. A message that
+/// continues a group only draws its content. Text matching `highlight` is
+/// marked. Returns a response covering the whole message, e.g. for a context menu.
+pub fn message(
+    ui: &mut Ui,
+    message: &Message,
+    clock: &Clock,
+    continued: bool,
+    highlight: &str,
+) -> egui::Response {
+    let now = database::current_timestamp();
+    let time = clock.time(message.timestamp_ms, now);
+    let color = author_color(&message.username, ui.visuals().dark_mode);
+
+    // Written with /me
+    if let Some(action) = message.content.strip_prefix("/me ") {
+        ui.add_space(4.0);
+        return ui
+            .horizontal_wrapped(|ui| {
+                ui.weak(time);
+                ui.label(
+                    RichText::new(format!("* {}", message.username))
+                        .color(color)
+                        .italics(),
+                );
+                text(ui, action, RichText::italics, highlight);
+            })
+            .response
+            .interact(Sense::click())
+            .on_hover_text(clock.full(message.timestamp_ms));
+    }
+
+    if !continued {
+        ui.add_space(4.0);
+    }
+
+    let response = ui
+        .vertical(|ui| {
+            if !continued {
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new(&message.username).color(color).strong());
+                    ui.weak(time)
+                        .on_hover_text(clock.full(message.timestamp_ms));
+                });
+            }
+
+            for block in &markdown::parse(&message.content) {
+                self::block(ui, block, highlight);
+            }
+        })
+        .response
+        .interact(Sense::click());
+
+    match continued {
+        true => response.on_hover_text(clock.full(message.timestamp_ms)),
+        false => response,
+    }
+}
+
+/// The color of a username. The same name always gets the same color.
+pub fn author_color(username: &str, dark: bool) -> Color32 {
+    const DARK: [Color32; 8] = [
+        Color32::from_rgb(255, 130, 120),
+        Color32::from_rgb(255, 180, 90),
+        Color32::from_rgb(220, 210, 100),
+        Color32::from_rgb(130, 210, 120),
+        Color32::from_rgb(100, 210, 200),
+        Color32::from_rgb(120, 170, 255),
+        Color32::from_rgb(190, 140, 255),
+        Color32::from_rgb(255, 140, 200),
+    ];
+    const LIGHT: [Color32; 8] = [
+        Color32::from_rgb(190, 40, 40),
+        Color32::from_rgb(190, 100, 0),
+        Color32::from_rgb(130, 120, 0),
+        Color32::from_rgb(30, 130, 30),
+        Color32::from_rgb(0, 130, 130),
+        Color32::from_rgb(30, 80, 200),
+        Color32::from_rgb(120, 50, 200),
+        Color32::from_rgb(190, 40, 130),
+    ];
+
+    // FNV-1a, so the color does not change between runs or versions
+    let hash = username.bytes().fold(0x811c9dc5u32, |hash, b| {
+        (hash ^ b as u32).wrapping_mul(0x01000193)
+    });
+
+    let palette = if dark { DARK } else { LIGHT };
+    palette[hash as usize % palette.len()]
+}
+
+/// Draws the line between the messages of two days.
+pub fn day_separator(ui: &mut Ui, label: &str) {
+    ui.add_space(4.0);
+    ui.vertical_centered(|ui| ui.weak(label));
+    ui.separator();
+}
+
+fn block(ui: &mut Ui, block: &Block, highlight: &str) {
+    match block {
+        Block::Line(line) => {
+            ui.horizontal_wrapped(|ui| spans(ui, line, highlight));
+        }
+        Block::Quote(quote) => {
+            ui.horizontal_wrapped(|ui| {
+                ui.weak("▌ ");
+                spans(ui, quote, highlight);
+            });
+        }
+        Block::Code { language, code } => code_block(ui, language, code),
+    }
+}
+
+fn spans(ui: &mut Ui, spans: &[Span], highlight: &str) {
+    ui.spacing_mut().item_spacing.x = 0.0;
+
+    for span in spans {
+        match span {
+            Span::Text(content) => text(ui, content, |t| t, highlight),
+            Span::Bold(content) => text(ui, content, RichText::strong, highlight),
+            Span::Italic(content) => text(ui, content, RichText::italics, highlight),
+            Span::Code(content) => {
+                ui.label(RichText::new(content).code());
+            }
+            Span::Link { text, url } => {
+                ui.hyperlink_to(text, url);
+            }
+        };
+    }
+}
+
+// Draws text in a style, with the parts matching `highlight` marked
+fn text(ui: &mut Ui, text: &str, style: impl Fn(RichText) -> RichText, highlight: &str) {
+    let marked = ui.visuals().selection.bg_fill;
+    let mut start = 0;
+
+    for range in filter::find(text, highlight) {
+        if range.start > start {
+            ui.label(style(RichText::new(&text[start..range.start])));
+        }
+        ui.label(style(RichText::new(&text[range.clone()])).background_color(marked));
+        start = range.end;
+    }
+
+    if start < text.len() {
+        ui.label(style(RichText::new(&text[start..])));
+    }
+}
+
+fn code_block(ui: &mut Ui, language: &str, code: &str) {
+    egui::Frame::none()
+        .fill(ui.visuals().code_bg_color)
+        .inner_margin(4.0)
+        .rounding(2.0)
+        .show(ui, |ui| {
+            ui.horizontal(|ui| {
+                ui.weak(language);
+                if ui.small_button("Copy").clicked() {
+                    ui.output_mut(|o| o.copied_text = code.to_owned());
+                }
+            });
+
+            let font = FontId::monospace(ui.style().text_styles[&egui::TextStyle::Body].size);
+            let mut job = LayoutJob::default();
+
+            for (token, text) in markdown::highlight(code, language) {
+                let format = TextFormat {
+                    font_id: font.clone(),
+                    color: color(ui, token),
+                    ..Default::default()
+                };
+                job.append(text, 0.0, format);
+            }
+
+            ui.label(job);
+        });
+}
+
+fn color(ui: &Ui, token: Token) -> Color32 {
+    let dark = ui.visuals().dark_mode;
+
+    match token {
+        Token::Plain => ui.visuals().text_color(),
+        Token::Keyword if dark => Color32::from_rgb(255, 120, 110),
+        Token::Keyword => Color32::from_rgb(200, 40, 40),
+        Token::String if dark => Color32::from_rgb(140, 210, 120),
+        Token::String => Color32::from_rgb(30, 130, 30),
+        Token::Number if dark => Color32::from_rgb(230, 190, 100),
+        Token::Number => Color32::from_rgb(170, 100, 0),
+        Token::Comment => ui.visuals().weak_text_color(),
+    }
+}
+
+//
+// Test Cases
+#[test]
+fn test_author_color() {
+    assert_eq!(author_color("alice", true), author_color("alice", true));
+    assert_ne!(author_color("alice", true), author_color("alice", false));
+
+    // Not everyone gets the same color
+    let names = ["alice", "bob", "carol", "dave", "erin"];
+    let first = author_color(names[0], true);
+    assert!(names.iter().any(|name| author_color(name, true) != first));
+}
+
+#[test]
+fn test_continues() {
+    let message = |username: &str, content: &str, timestamp_ms| Message {
+        timestamp_ms,
+        ..Message::new(username.to_owned(), content.to_owned())
+    };
+    let first = message("alice", "hi", 0);
+
+    assert!(continues(&first, &message("alice", "there", 60_000)));
+    assert!(!continues(&first, &message("bob", "hey", 60_000)));
+    assert!(!continues(&first, &message("alice", "later", GROUP_GAP_MS)));
+    assert!(!continues(&first, &message("alice", "/me waves", 60_000)));
+}
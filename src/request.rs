@@ -1,9 +1,15 @@
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Instant;
 
 use crate::{
     database::{self, message::to_messages, user::to_users},
-    message::{Message, User},
+    message::{can_read, parse_mentions, Message, Profile, Role, ServerInfo, Unread, User},
+    moderation::{self, Action},
+    network::PROTOCOL_VERSION,
     response::Response,
+    server::ServerState,
+    timestamp::Zone,
 };
 
 /// The client sends a request to the server.
@@ -19,39 +25,403 @@ pub enum Request {
     GetUsers(),
     AddUser(User),
     RemoveUser(User),
+    PinMessage(i32),
+    UnpinMessage(i32),
+    GetProfile(String),
+    UpdateProfile(Profile),
+    Kick(String, String), // Username, reason
+    Ban(String, String),  // Username, reason
+    Unban(String),
+    Mute(String),
+    Unmute(String),
+    SetRole(String, Role),
+    GetBans(),
+    GetAuditLog(u32),
+    GetChannels(),
+    GetChannelMessages(String),
+    GetCommands(),
+    RunCommand(String, String, String), // Channel, command name, arguments
+    Ping(u64),                          // Answered with a Pong carrying the same value
+    Typing(String, bool),               // Channel, started or stopped
+    GetTyping(String),                  // Channel
+    MarkRead(String, i32),              // Channel, id of the last message shown
+    GetUnread(),
+    GetReaders(String, i32), // Channel, message id
+    GetMentions(u32),        // The newest messages that mention us, at most this many
+    GetServerInfo(),
+    Pong(u64), // Answers the server's Ping, the server does not reply
 }
 
-pub fn handle_request(request: Request) -> Response {
+/// Session is the state the server keeps for a single connection.
+#[derive(Debug)]
+pub struct Session {
+    pub username: Option<String>, // Set once the client has added its user
+    pub state: Arc<ServerState>,
+}
+
+impl Session {
+    pub fn new(state: Arc<ServerState>) -> Session {
+        Session {
+            username: None,
+            state,
+        }
+    }
+
+    /// The role of the logged in user
+    fn role(&self) -> Option<Role> {
+        self.username.as_deref().map(database::user::role)
+    }
+
+    /// Returns true if this session may read the channel
+    fn can_read(&self, channel: &str) -> bool {
+        can_read(channel, self.username.as_deref().unwrap_or_default())
+    }
+
+    /// Drops the messages this session may not read
+    fn visible(&self, messages: Vec<Message>) -> Vec<Message> {
+        messages
+            .into_iter()
+            .filter(|message| self.can_read(&message.channel))
+            .collect()
+    }
+}
+
+/// leave() logs the session's user out, e.g. when the client disconnects.
+pub fn leave(session: &mut Session) {
+    if let Some(username) = session.username.take() {
+        database::user::set_online(&username, false);
+        session.state.typing.remove_user(&username);
+        post(session.state.plugins.on_leave(&username));
+    }
+}
+
+// Stores messages posted by plugins
+fn post(messages: Vec<Message>) {
+    for mut message in messages {
+        message.pinned = false;
+        message.mentions = mentions(&message.content);
+        database::message::add_message(message);
+    }
+}
+
+// The users mentioned in a message who actually exist
+fn mentions(content: &str) -> Vec<String> {
+    parse_mentions(content)
+        .into_iter()
+        .filter(|username| database::user::get(username).is_some())
+        .collect()
+}
+
+pub fn handle_request(request: Request, session: &mut Session) -> Response {
     match request {
-        Request::AddMessage(message) => {
-            database::message::add_message(message);
-            Response::OK
+        Request::AddMessage(mut message) => {
+            let Some(username) = &session.username else {
+                return Response::Error("You must log in first".to_owned());
+            };
+            if !database::user::role(username).can_post() {
+                return Response::Error("You are muted".to_owned());
+            }
+            if !session.can_read(&message.channel) {
+                return Response::Error("You cannot post in this channel".to_owned());
+            }
+
+            // Messages are always sent as the logged in user, and only
+            // moderators pin them, with PinMessage
+            message.username = username.clone();
+            message.pinned = false;
+
+            match session.state.plugins.on_message(&mut message) {
+                Ok(replies) => {
+                    // Sending the message is the end of typing it
+                    session
+                        .state
+                        .typing
+                        .stop(&message.channel, &message.username);
+                    message.mentions = mentions(&message.content);
+                    database::message::add_message(message);
+                    post(replies);
+                    Response::OK
+                }
+                Err(e) => Response::Error(e),
+            }
         }
         Request::LastMessages(n) => {
             let messages = to_messages(database::message::select_last(n));
-            Response::Messages(messages)
+            Response::Messages(session.visible(messages))
         }
         Request::GetMessages() => {
             let messages = to_messages(database::message::select_all());
-            Response::Messages(messages)
+            Response::Messages(session.visible(messages))
         }
         Request::AfterTimestamp(n) => {
             let messages = to_messages(database::message::select_after(n));
-            Response::Messages(messages)
+            Response::Messages(session.visible(messages))
         }
         Request::GetMessageAtIndex(_) => todo!(),
 
         Request::AddUser(user) => {
-            database::user::add_user(user);
-            Response::OK
+            let username = user.username.trim().to_owned();
+
+            if session.username.as_ref() == Some(&username) {
+                let token = database::user::get(&username).and_then(|row| row.token);
+                return Response::Token(token.unwrap_or_default());
+            }
+            if database::moderation::is_username_banned(&username) {
+                return Response::Error("You are banned from this server".to_owned());
+            }
+
+            let token = match database::user::add_user(user) {
+                Ok(token) => token,
+                Err(e) => return Response::Error(e),
+            };
+
+            // Plugins only hear about names that were free, a rejection frees it again
+            let replies = match session.state.plugins.on_join(&username) {
+                Ok(replies) => replies,
+                Err(e) => {
+                    database::user::set_online(&username, false);
+                    return Response::Error(e);
+                }
+            };
+
+            // Switching users frees the previous name
+            leave(session);
+            session.username = Some(username);
+            post(replies);
+            Response::Token(token)
         }
         Request::GetUsers() => {
-            let user = to_users(database::user::select_all());
+            let user = to_users(database::user::select_online());
             Response::Users(user)
         }
         Request::RemoveUser(user) => {
-            database::user::remove(user.username);
+            if session.username.as_ref() != Some(&user.username) {
+                return Response::Error("You can only remove your own user".to_owned());
+            }
+            leave(session);
+            Response::OK
+        }
+
+        Request::PinMessage(_) | Request::UnpinMessage(_)
+            if !session.role().is_some_and(Role::can_moderate) =>
+        {
+            Response::Error("Only moderators can pin messages".to_owned())
+        }
+        Request::PinMessage(id) => {
+            database::message::set_pinned(id as i64, true);
             Response::OK
         }
+        Request::UnpinMessage(id) => {
+            database::message::set_pinned(id as i64, false);
+            Response::OK
+        }
+
+        Request::GetProfile(username) => match database::user::get(&username) {
+            Some(row) => Response::Profile(row.into()),
+            None => Response::Error(format!("No user named {}", username)),
+        },
+        Request::UpdateProfile(profile) => {
+            if session.username.as_ref() != Some(&profile.username) {
+                return Response::Error("You can only edit your own profile".to_owned());
+            }
+            if Zone::parse(&profile.timezone).is_none() {
+                return Response::Error(format!(
+                    "Unknown timezone {:?}, use a name like Europe/Berlin or an offset like +02:00",
+                    profile.timezone
+                ));
+            }
+            database::user::update_profile(profile);
+            Response::OK
+        }
+
+        Request::Kick(username, reason) => {
+            moderation::moderate(Action::Kick, username, reason, session)
+        }
+        Request::Ban(username, reason) => {
+            moderation::moderate(Action::Ban, username, reason, session)
+        }
+        Request::Unban(username) => {
+            moderation::moderate(Action::Unban, username, String::new(), session)
+        }
+        Request::Mute(username) => {
+            moderation::moderate(Action::Mute, username, String::new(), session)
+        }
+        Request::Unmute(username) => {
+            moderation::moderate(Action::Unmute, username, String::new(), session)
+        }
+        Request::SetRole(username, role) => {
+            moderation::moderate(Action::SetRole(role), username, String::new(), session)
+        }
+        Request::GetBans() | Request::GetAuditLog(_)
+            if !session.role().is_some_and(Role::can_moderate) =>
+        {
+            Response::Error("You are not a moderator".to_owned())
+        }
+        Request::GetBans() => Response::Bans(database::moderation::select_bans()),
+        Request::GetAuditLog(n) => Response::AuditLog(database::moderation::select_audit(n)),
+
+        Request::GetChannels() => {
+            let channels = database::channel::select_all()
+                .into_iter()
+                .filter(|channel| session.can_read(&channel.name))
+                .collect();
+            Response::Channels(channels)
+        }
+        Request::GetChannelMessages(channel) => {
+            if !session.can_read(&channel) {
+                return Response::Error("You cannot read this channel".to_owned());
+            }
+            let messages = to_messages(database::message::select_channel(&channel));
+            Response::Messages(messages)
+        }
+        Request::GetCommands() => Response::Commands(session.state.commands.infos()),
+        Request::RunCommand(channel, name, args) => {
+            if !session.can_read(&channel) {
+                return Response::Error("You cannot use commands in this channel".to_owned());
+            }
+            let state = session.state.clone();
+            match state.commands.get(&name) {
+                Some(command) => command(&args, &channel, session),
+                None => Response::Error(format!("Unknown command /{}", name)),
+            }
+        }
+        Request::Ping(n) => Response::Pong(n),
+        Request::Pong(_) => Response::OK, // Taken by the connection loop before it gets here
+        Request::Typing(channel, started) => {
+            let Some(username) = &session.username else {
+                return Response::Error("You must log in first".to_owned());
+            };
+            if !session.can_read(&channel) {
+                return Response::Error("You cannot post in this channel".to_owned());
+            }
+
+            match started {
+                true => session
+                    .state
+                    .typing
+                    .start(&channel, username, Instant::now()),
+                false => session.state.typing.stop(&channel, username),
+            }
+            Response::OK
+        }
+        Request::MarkRead(channel, id) => {
+            let Some(username) = &session.username else {
+                return Response::Error("You must log in first".to_owned());
+            };
+            if !session.can_read(&channel) {
+                return Response::Error("You cannot read this channel".to_owned());
+            }
+            database::read::mark_read(username, &channel, id as i64);
+            Response::OK
+        }
+        Request::GetUnread() => {
+            let Some(username) = &session.username else {
+                return Response::Error("You must log in first".to_owned());
+            };
+            let unread = database::channel::select_all()
+                .into_iter()
+                .filter(|channel| session.can_read(&channel.name))
+                .map(|channel| Unread {
+                    last_read: database::read::last_read(username, &channel.name) as i32,
+                    count: database::read::count_unread(username, &channel.name),
+                    channel: channel.name,
+                })
+                .collect();
+            Response::Unread(unread)
+        }
+        Request::GetReaders(channel, id) => {
+            if session.username.is_none() {
+                return Response::Error("You must log in first".to_owned());
+            }
+            if !session.can_read(&channel) {
+                return Response::Error("You cannot read this channel".to_owned());
+            }
+            Response::Readers(database::read::select_readers(&channel, id as i64))
+        }
+        Request::GetMentions(n) => {
+            let Some(username) = &session.username else {
+                return Response::Error("You must log in first".to_owned());
+            };
+            // Only count the ones we may read towards the limit
+            let messages = to_messages(database::message::select_mentions(username));
+            let mut messages = session.visible(messages);
+            messages.truncate(n as usize);
+            Response::Messages(messages)
+        }
+        Request::GetTyping(channel) => {
+            if !session.can_read(&channel) {
+                return Response::Error("You cannot read this channel".to_owned());
+            }
+            let username = session.username.as_deref().unwrap_or_default();
+            Response::Typing(session.state.typing.who(&channel, username, Instant::now()))
+        }
+        Request::GetServerInfo() => Response::ServerInfo(ServerInfo {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            protocol: PROTOCOL_VERSION,
+            uptime_secs: session.state.started.elapsed().as_secs(),
+            motd: session.state.motd.clone(),
+        }),
     }
 }
+
+//
+// Test Cases
+#[test]
+fn test_update_profile_timezone() {
+    let mut session = Session::new(Arc::new(ServerState::new()));
+    session.username = Some("zone_test".to_owned());
+
+    let profile = |timezone: &str| Profile {
+        username: "zone_test".to_owned(),
+        timezone: timezone.to_owned(),
+        ..Default::default()
+    };
+
+    let response = handle_request(Request::UpdateProfile(profile("Mars/Base")), &mut session);
+    assert!(matches!(response, Response::Error(_)));
+    let response = handle_request(Request::UpdateProfile(profile("+02:00")), &mut session);
+    assert!(matches!(response, Response::OK));
+}
+
+#[test]
+fn test_messages_are_not_posted_pinned() {
+    let mut session = Session::new(Arc::new(ServerState::new()));
+    session.username = Some("pin_test".to_owned());
+
+    // The test database outlives the test, so use a new channel every run
+    let channel = format!("pin-test-{}", database::current_timestamp());
+    let mut message = Message::new("pin_test".to_owned(), "pinned by me".to_owned());
+    message.channel = channel.clone();
+    message.pinned = true;
+
+    let response = handle_request(Request::AddMessage(message), &mut session);
+    assert!(matches!(response, Response::OK));
+
+    let stored = to_messages(database::message::select_channel(&channel));
+    assert_eq!(stored.len(), 1);
+    assert!(!stored[0].pinned);
+}
+
+#[test]
+fn test_existing_usernames_need_their_token() {
+    let username = format!("token_test_{}", database::current_timestamp());
+    let user = |token: &str| User {
+        id: 0,
+        username: username.clone(),
+        token: token.to_owned(),
+    };
+
+    let mut session = Session::new(Arc::new(ServerState::new()));
+    let Response::Token(token) = handle_request(Request::AddUser(user("")), &mut session) else {
+        panic!("The first login should be given a token");
+    };
+    leave(&mut session);
+
+    // Someone else can't take over the name while it is offline
+    let mut other = Session::new(Arc::new(ServerState::new()));
+    let response = handle_request(Request::AddUser(user("guess")), &mut other);
+    assert!(matches!(response, Response::Error(_)));
+
+    let response = handle_request(Request::AddUser(user(&token)), &mut other);
+    assert!(matches!(response, Response::Token(t) if t == token));
+}
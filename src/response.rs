@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 
-use crate::message::{Message, User};
+use crate::command::CommandInfo;
+use crate::message::{AuditEntry, Ban, Channel, Message, Profile, ServerInfo, Unread, User};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Response {
@@ -8,4 +9,18 @@ pub enum Response {
     Error(String),
     Messages(Vec<Message>),
     Users(Vec<User>),
+    Profile(Profile),
+    Bans(Vec<Ban>),
+    AuditLog(Vec<AuditEntry>),
+    Channels(Vec<Channel>),
+    Commands(Vec<CommandInfo>),
+    Text(String),         // Output of a command, to show the user
+    ShuttingDown(String), // Sent instead of a response when the server stops
+    Pong(u64),
+    Typing(Vec<String>), // Usernames
+    Unread(Vec<Unread>),
+    Readers(Vec<String>), // Usernames
+    ServerInfo(ServerInfo),
+    Ping(u64),     // Sent by the server to an idle client, answered with Request::Pong
+    Token(String), // Answers AddUser, the token to log in with next time
 }
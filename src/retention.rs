@@ -0,0 +1,122 @@
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::database::{self, message::to_messages, MessageRow};
+use crate::history::{self, Format};
+use crate::message::Message;
+use crate::Args;
+
+const MS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+/// RetentionPolicy decides which messages the server is allowed to forget.
+/// Pinned messages are always kept.
+#[derive(Debug, Clone, Default)]
+pub struct RetentionPolicy {
+    pub max_age_days: Option<u32>,
+    pub max_count: Option<u32>, // Per channel
+    pub archive: Option<PathBuf>,
+}
+
+impl RetentionPolicy {
+    pub fn from_args(args: &Args) -> RetentionPolicy {
+        RetentionPolicy {
+            max_age_days: args.retention_days,
+            max_count: args.retention_count,
+            archive: args.archive.clone(),
+        }
+    }
+
+    /// Returns true when there is anything to prune.
+    pub fn is_enabled(&self) -> bool {
+        self.max_age_days.is_some() || self.max_count.is_some()
+    }
+
+    /// Removes every message that falls outside the policy, archiving it first
+    /// if an archive file is configured. Returns the number of removed messages.
+    pub fn prune(&self) -> usize {
+        let mut rows: Vec<MessageRow> = vec![];
+
+        if let Some(days) = self.max_age_days {
+            let cutoff = database::current_timestamp() - days as i64 * MS_PER_DAY;
+            rows.extend(database::message::select_before(cutoff));
+        }
+
+        if let Some(max) = self.max_count {
+            rows.extend(database::message::select_over_limit(max));
+        }
+
+        // A message can be both too old and over the limit
+        rows.sort_by_key(|row| row.rowid);
+        rows.dedup_by_key(|row| row.rowid);
+
+        if rows.is_empty() {
+            return 0;
+        }
+
+        let ids: Vec<i64> = rows.iter().filter_map(|row| row.rowid).collect();
+
+        if let Some(path) = &self.archive {
+            // Never delete what we failed to archive
+            if let Err(e) = archive(path, &to_messages(rows)) {
+                eprintln!("Could not archive messages to {}: {}", path.display(), e);
+                return 0;
+            }
+        }
+
+        ids.into_iter()
+            .filter(|id| database::message::delete_unpinned(*id))
+            .count()
+    }
+}
+
+/// Appends messages to the archive file as JSON Lines,
+/// which `import --format jsonl` can read back.
+pub fn archive(path: &Path, messages: &[Message]) -> std::io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut writer = BufWriter::new(file);
+
+    history::write_messages(&mut writer, Format::Jsonl, messages)?;
+    writer.flush()
+}
+
+/// spawn_pruner() starts a background thread that applies the policy
+/// every `interval` until `running` is cleared.
+pub fn spawn_pruner(policy: Arc<RetentionPolicy>, interval: Duration, running: Arc<AtomicBool>) {
+    std::thread::spawn(move || {
+        while running.load(Ordering::Relaxed) {
+            let removed = policy.prune();
+
+            if removed > 0 {
+                println!("Retention removed {} messages", removed);
+            }
+
+            std::thread::sleep(interval);
+        }
+    });
+}
+
+//
+// Test Cases
+#[test]
+fn test_archive_appends_json_lines() {
+    let path = std::env::temp_dir().join("rust_chatter_test_archive.jsonl");
+    let _ = std::fs::remove_file(&path);
+
+    let message = Message::new("bob".to_string(), "first".to_string());
+    archive(&path, &[message]).unwrap();
+
+    let message = Message::new("bob".to_string(), "second".to_string());
+    archive(&path, &[message]).unwrap();
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = contents.lines().collect();
+
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].contains("\"second\""));
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -1,35 +1,511 @@
 // use rusqlite::Connection;
-use std::net::TcpStream;
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
-use crate::network;
-use crate::request::handle_request;
+use crate::command::Registry;
+use crate::database::{self, DatabasePath};
+use crate::network::{self, Heartbeat, Received};
+use crate::plugin::{Announcer, Plugin, Plugins};
+use crate::request::{self, handle_request, Request, Session};
+use crate::response::Response;
+use crate::retention::{self, RetentionPolicy};
+use crate::server_commands::{self, ServerCommand};
+use crate::typing::Typing;
 use crate::Args;
 
-/// setup_server() is a helper function that sets up connection listeners,
-/// and spawns a new thread for each connection.
+/// ServerState is shared by every connection on the server.
+#[derive(Debug)]
+pub struct ServerState {
+    connections: Mutex<BTreeMap<String, Arc<TcpStream>>>, // By username
+    pub commands: Registry<ServerCommand>,
+    pub plugins: Plugins,
+    pub heartbeat: Heartbeat, // Connections are dropped after the timeout
+    pub typing: Typing,
+    pub motd: String,         // Message of the day
+    pub started: Instant,     // For the uptime
+    running: Arc<AtomicBool>, // Cleared when the server shuts down
+}
+
+impl Default for ServerState {
+    fn default() -> Self {
+        ServerState::new()
+    }
+}
+
+impl ServerState {
+    pub fn new() -> ServerState {
+        ServerState {
+            connections: Mutex::new(BTreeMap::new()),
+            commands: server_commands::builtin(),
+            plugins: Plugins::default(),
+            heartbeat: Heartbeat::default(),
+            typing: Typing::default(),
+            motd: String::new(),
+            started: Instant::now(),
+            running: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    fn register(&self, username: &str, conn: Arc<TcpStream>) {
+        let mut connections = self.connections.lock().unwrap();
+        connections.insert(username.to_owned(), conn);
+    }
+
+    fn unregister(&self, username: &str, conn: &Arc<TcpStream>) {
+        let mut connections = self.connections.lock().unwrap();
+
+        // The user may already be back on a new connection
+        if connections
+            .get(username)
+            .is_some_and(|registered| Arc::ptr_eq(registered, conn))
+        {
+            connections.remove(username);
+        }
+    }
+
+    /// The address a user is connected from, if they are connected
+    pub fn address(&self, username: &str) -> Option<String> {
+        let connections = self.connections.lock().unwrap();
+        let conn = connections.get(username)?;
+        conn.peer_addr().ok().map(|addr| addr.ip().to_string())
+    }
+
+    /// Closes a user's connection. Returns false if they were not connected.
+    pub fn disconnect(&self, username: &str) -> bool {
+        let connections = self.connections.lock().unwrap();
+
+        match connections.get(username) {
+            Some(conn) => conn.shutdown(Shutdown::Both).is_ok(),
+            None => false,
+        }
+    }
+}
+
+/// setup_server() runs a server configured from the command line
+/// until it shuts down.
 pub fn setup_server(args: Arc<Args>) {
-    let address = "0.0.0.0:".to_owned() + &args.port;
-    let listener = std::net::TcpListener::bind(address).unwrap();
+    let mut server = ChatServer::new()
+        .bind(&format!("0.0.0.0:{}", args.port))
+        .heartbeat(args.heartbeat())
+        .motd(&args.motd);
 
-    loop {
-        let (conn, _) = listener.accept().unwrap();
-        std::thread::spawn(|| server(conn));
+    let policy = RetentionPolicy::from_args(&args);
+    if policy.is_enabled() {
+        server = server.retention(policy, Duration::from_secs(args.prune_interval));
+    }
+
+    if args.announce {
+        server = server.plugin(Box::new(Announcer));
+    }
+
+    // Nobody is connected yet, whatever the last run left behind. Embedded
+    // servers skip this, another server may share the database.
+    database::user::clear_presence();
+
+    match server.start() {
+        Ok(handle) => {
+            println!("Listening on {}", handle.address());
+
+            // Ctrl-C or SIGTERM stops the server cleanly, a second one right away
+            let stopper = handle.stopper();
+            let mut stopping = false;
+            let result = ctrlc::set_handler(move || {
+                if stopping {
+                    eprintln!("Exiting without waiting for clients");
+                    std::process::exit(1);
+                }
+                stopping = true;
+                println!("Shutting down, press Ctrl-C again to exit right away");
+                stopper.stop();
+            });
+            if let Err(e) = result {
+                eprintln!("Could not handle signals: {}", e);
+            }
+
+            handle.wait();
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// ChatServer builds a server that can run inside another process,
+/// e.g. a test. Nothing happens until `start()` is called.
+pub struct ChatServer {
+    address: String,
+    database: DatabasePath,
+    retention: Option<(RetentionPolicy, Duration)>,
+    plugins: Plugins,
+    heartbeat: Heartbeat,
+    motd: String,
+}
+
+impl Default for ChatServer {
+    fn default() -> Self {
+        ChatServer::new()
+    }
+}
+
+impl ChatServer {
+    pub fn new() -> ChatServer {
+        ChatServer {
+            address: "0.0.0.0:23432".to_owned(),
+            database: DatabasePath::default(),
+            retention: None,
+            plugins: Plugins::default(),
+            heartbeat: Heartbeat::default(),
+            motd: String::new(),
+        }
+    }
+
+    /// The address to listen on. Port 0 picks a free port,
+    /// see `ServerHandle::address()`.
+    pub fn bind(mut self, address: &str) -> ChatServer {
+        self.address = address.to_owned();
+        self
+    }
+
+    /// The database file to use. Every server in a process shares one
+    /// database, so this fails `start()` once it is open.
+    pub fn database(mut self, path: DatabasePath) -> ChatServer {
+        self.database = path;
+        self
+    }
+
+    /// Prunes messages every `interval` while the server runs.
+    pub fn retention(mut self, policy: RetentionPolicy, interval: Duration) -> ChatServer {
+        self.retention = Some((policy, interval));
+        self
+    }
+
+    pub fn plugin(mut self, plugin: Box<dyn Plugin>) -> ChatServer {
+        self.plugins.add(plugin);
+        self
+    }
+
+    /// Clients that send nothing for `heartbeat.timeout` are disconnected.
+    pub fn heartbeat(mut self, heartbeat: Heartbeat) -> ChatServer {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    /// The message of the day, shown in the clients' About window.
+    pub fn motd(mut self, motd: &str) -> ChatServer {
+        self.motd = motd.to_owned();
+        self
+    }
+
+    /// Opens the database, binds the address and starts accepting
+    /// connections on a background thread.
+    pub fn start(self) -> Result<ServerHandle, String> {
+        self.database.open()?;
+
+        let listener = TcpListener::bind(&self.address)
+            .map_err(|e| format!("Could not listen on {}: {}", self.address, e))?;
+        let address = listener.local_addr().map_err(|e| e.to_string())?;
+
+        let mut state = ServerState::new();
+        state.plugins = self.plugins;
+        state.heartbeat = self.heartbeat;
+        state.motd = self.motd;
+        let state = Arc::new(state);
+
+        if let Some((policy, interval)) = self.retention {
+            if policy.is_enabled() {
+                retention::spawn_pruner(Arc::new(policy), interval, state.running.clone());
+            }
+        }
+
+        let thread = {
+            let state = state.clone();
+            std::thread::spawn(move || accept(listener, state))
+        };
+
+        Ok(ServerHandle {
+            address,
+            state,
+            thread,
+        })
+    }
+}
+
+/// ServerHandle controls a running server.
+pub struct ServerHandle {
+    address: SocketAddr,
+    state: Arc<ServerState>,
+    thread: JoinHandle<()>,
+}
+
+impl ServerHandle {
+    /// The address the server is listening on.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Something that can stop the server from another thread,
+    /// e.g. a signal handler.
+    pub fn stopper(&self) -> Stopper {
+        Stopper {
+            address: self.address,
+            state: self.state.clone(),
+        }
+    }
+
+    /// Shuts the server down, see `Stopper::stop()`,
+    /// and waits for it to finish.
+    pub fn shutdown(self) {
+        self.stopper().stop();
+        self.wait();
     }
+
+    /// Blocks until the server has shut down.
+    pub fn wait(self) {
+        let _ = self.thread.join();
+    }
+}
+
+/// Stopper stops a running server.
+#[derive(Debug, Clone)]
+pub struct Stopper {
+    address: SocketAddr,
+    state: Arc<ServerState>,
+}
+
+impl Stopper {
+    /// Stops accepting connections. Requests that are being handled are
+    /// answered, then every client is told that the server is shutting down.
+    /// It returns right away, `ServerHandle::wait()` waits for the rest.
+    pub fn stop(&self) {
+        self.state.running.store(false, Ordering::Relaxed);
+
+        // Wake up the accept loop so it sees that we are stopping
+        let mut address = self.address;
+        if address.ip().is_unspecified() {
+            address.set_ip(Ipv4Addr::LOCALHOST.into());
+        }
+        let _ = TcpStream::connect(address);
+    }
+}
+
+// Accepts connections until the server is shut down, then closes them all.
+fn accept(listener: TcpListener, state: Arc<ServerState>) {
+    let mut workers: Vec<(Arc<TcpStream>, JoinHandle<()>)> = vec![];
+
+    for conn in listener.incoming() {
+        if !state.running.load(Ordering::Relaxed) {
+            break;
+        }
+
+        let Ok(conn) = conn else {
+            continue;
+        };
+        let Ok(addr) = conn.peer_addr() else {
+            continue;
+        };
+
+        // Banned addresses are turned away before they can log in
+        if database::moderation::is_address_banned(&addr.ip().to_string()) {
+            let response = Response::Error("You are banned from this server".to_owned());
+            network::send(response, Arc::new(conn));
+            continue;
+        }
+
+        workers.retain(|(_, worker)| !worker.is_finished());
+
+        let conn = Arc::new(conn);
+        let session = Session::new(state.clone());
+        let worker = {
+            let conn = conn.clone();
+            std::thread::spawn(move || server(conn, session))
+        };
+        workers.push((conn, worker));
+    }
+
+    // Stop reading so every connection finishes its request, tells its
+    // client that we are shutting down and hangs up
+    for (conn, _) in &workers {
+        let _ = conn.shutdown(Shutdown::Read);
+    }
+    for (_, worker) in workers {
+        let _ = worker.join();
+    }
+
+    database::checkpoint();
 }
 
 /// server() is the main function for the server.
-fn server(conn: TcpStream) {
-    let connection: Arc<TcpStream> = Arc::new(conn);
+fn server(connection: Arc<TcpStream>, mut session: Session) {
+    // A quiet client is pinged, one that doesn't answer is gone
+    // even if the socket looks open
+    let timeout = Some(session.state.heartbeat.timeout);
+    let _ = connection.set_read_timeout(timeout);
+    let _ = connection.set_write_timeout(timeout);
+    let mut pings = 0;
+    let mut waiting = false;
 
     // Read, Handle Request, Write, Loop
+    // Until the client disconnects
     loop {
-        let request_result = network::get(connection.clone());
+        let request = match network::receive(&connection) {
+            Received::Value(request) => request,
+            Received::Idle if !waiting => {
+                pings += 1;
+                waiting = true;
+                network::send(Response::Ping(pings), connection.clone());
+                continue;
+            }
+            Received::Idle | Received::Closed => break,
+        };
 
-        if let Some(request) = request_result {
-            let response = handle_request(request);
+        // Anything the client sends shows it is still there
+        waiting = false;
+        if let Request::Pong(_) = request {
+            continue;
+        }
+
+        let previous = session.username.clone();
+        let response = handle_request(request, &mut session);
 
-            network::send(response, connection.clone());
+        // Keep track of who is on this connection so they can be kicked
+        if session.username != previous {
+            if let Some(username) = &previous {
+                session.state.unregister(username, &connection);
+            }
+            if let Some(username) = &session.username {
+                session.state.register(username, connection.clone());
+            }
         }
+
+        network::send(response, connection.clone());
+    }
+
+    // Tell the client why we are hanging up, idle clients read it too
+    if !session.state.running.load(Ordering::Relaxed) {
+        let response = Response::ShuttingDown("The server is shutting down".to_owned());
+        network::send(response, connection.clone());
+    }
+
+    // The accept loop still holds the connection, so close it here
+    let _ = connection.shutdown(Shutdown::Both);
+
+    // The client may have gone away without removing its user
+    if let Some(username) = &session.username {
+        session.state.unregister(username, &connection);
     }
+    request::leave(&mut session);
+}
+
+//
+// Test Cases
+#[test]
+fn test_embedded_server() {
+    use crate::chat_client::ChatClient;
+
+    let handle = ChatServer::new()
+        .bind("127.0.0.1:0")
+        .motd("Welcome")
+        .start()
+        .unwrap();
+    let address = handle.address().to_string();
+    assert_ne!(handle.address().port(), 0);
+
+    // Usernames are kept by their token, so use a new one every run
+    let username = format!("embedded_test_{}", database::current_timestamp());
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let client = runtime.block_on(async {
+        let mut client = ChatClient::connect(&address).await.unwrap();
+        client.login(&username).await.unwrap();
+        client.send("embedded-test", "hello").await.unwrap();
+
+        let history = client.history("embedded-test").await.unwrap();
+        assert_eq!(history.last().unwrap().content, "hello");
+
+        let info = client.server_info().await.unwrap();
+        assert_eq!(info.motd, "Welcome");
+        assert_eq!(info.protocol, network::PROTOCOL_VERSION);
+
+        let diagnostics = client.diagnostics();
+        assert!(diagnostics.bytes_sent > 0 && diagnostics.bytes_received > 0);
+        assert_eq!(diagnostics.reconnects, 0);
+        client
+    });
+
+    handle.shutdown();
+
+    // Connected clients are told why
+    let error = runtime.block_on(client.users()).unwrap_err();
+    assert!(error.contains("shutting down"));
+
+    // Nobody is listening anymore
+    assert!(TcpStream::connect(&address).is_err());
+}
+
+#[test]
+fn test_heartbeat_timeout() {
+    use crate::message::User;
+
+    let heartbeat = Heartbeat {
+        interval: Duration::from_millis(50),
+        timeout: Duration::from_millis(200),
+    };
+    let handle = ChatServer::new()
+        .bind("127.0.0.1:0")
+        .heartbeat(heartbeat)
+        .start()
+        .unwrap();
+
+    // ChatClient answers pings, so log in by hand and then say nothing
+    let username = format!("heartbeat_test_{}", database::current_timestamp());
+    let conn = Arc::new(TcpStream::connect(handle.address()).unwrap());
+    let user = User {
+        id: 0,
+        username: username.clone(),
+        token: String::new(),
+    };
+    let response: Option<Response> = network::request(Request::AddUser(user), conn.clone());
+    assert!(matches!(response, Some(Response::Token(_))));
+
+    // A silent client is pinged, then dropped and marked offline
+    std::thread::sleep(Duration::from_millis(600));
+    let response: Option<Response> = network::get(conn.clone());
+    assert!(matches!(response, Some(Response::Ping(_))));
+    let response: Option<Response> = network::get(conn);
+    assert!(response.is_none());
+
+    let row = database::user::get(&username).unwrap();
+    assert_eq!(row.online, Some(false));
+
+    handle.shutdown();
+}
+
+#[test]
+fn test_server_pings_idle_clients() {
+    use crate::chat_client::ChatClient;
+
+    let heartbeat = Heartbeat {
+        interval: Duration::from_secs(60),
+        timeout: Duration::from_millis(300),
+    };
+    let handle = ChatServer::new()
+        .bind("127.0.0.1:0")
+        .heartbeat(heartbeat)
+        .start()
+        .unwrap();
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let username = format!("server_ping_test_{}", database::current_timestamp());
+    let client = ChatClient::offline(&handle.address().to_string(), &username);
+    runtime.block_on(client.reconnect()).unwrap();
+
+    // The server's pings are answered while we are idle, well past the timeout
+    std::thread::sleep(Duration::from_millis(900));
+    runtime.block_on(client.ping()).unwrap();
+
+    handle.shutdown();
 }
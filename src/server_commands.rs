@@ -0,0 +1,117 @@
+use crate::command::{split_first, CommandInfo, Registry};
+use crate::database;
+use crate::moderation::{self, Action};
+use crate::request::Session;
+use crate::response::Response;
+
+/// ServerCommand handles a slash command sent with `Request::RunCommand`.
+/// It gets the arguments and the channel the command was typed in.
+pub type ServerCommand = fn(args: &str, channel: &str, session: &mut Session) -> Response;
+
+/// The commands every server has. They are advertised to clients.
+pub fn builtin() -> Registry<ServerCommand> {
+    let mut commands: Registry<ServerCommand> = Registry::default();
+
+    commands.register(
+        CommandInfo::new("topic", "[topic]", "Show or set the channel topic"),
+        topic,
+    );
+    commands.register(
+        CommandInfo::new("kick", "<user> [reason]", "Disconnect a user"),
+        kick,
+    );
+    commands.register(
+        CommandInfo::new("ban", "<user> [reason]", "Ban a user and their address"),
+        ban,
+    );
+    commands.register(CommandInfo::new("unban", "<user>", "Lift a ban"), unban);
+    commands.register(
+        CommandInfo::new("mute", "<user>", "Stop a user from posting"),
+        mute,
+    );
+    commands.register(
+        CommandInfo::new("unmute", "<user>", "Let a muted user post again"),
+        unmute,
+    );
+
+    commands
+}
+
+fn topic(args: &str, channel: &str, session: &mut Session) -> Response {
+    if args.is_empty() {
+        let topic = database::channel::get(channel)
+            .and_then(|row| row.topic)
+            .filter(|topic| !topic.is_empty());
+
+        return match topic {
+            Some(topic) => Response::Text(format!("#{}: {}", channel, topic)),
+            None => Response::Text(format!("#{} has no topic", channel)),
+        };
+    }
+
+    let Some(username) = &session.username else {
+        return Response::Error("You must log in first".to_owned());
+    };
+    if !database::user::role(username).can_post() {
+        return Response::Error("You are muted".to_owned());
+    }
+
+    database::channel::set_topic(channel, args, username);
+    Response::OK
+}
+
+fn kick(args: &str, _channel: &str, session: &mut Session) -> Response {
+    let (username, reason) = split_first(args);
+    moderation::moderate(
+        Action::Kick,
+        username.to_owned(),
+        reason.to_owned(),
+        session,
+    )
+}
+
+fn ban(args: &str, _channel: &str, session: &mut Session) -> Response {
+    let (username, reason) = split_first(args);
+    moderation::moderate(Action::Ban, username.to_owned(), reason.to_owned(), session)
+}
+
+fn unban(args: &str, _channel: &str, session: &mut Session) -> Response {
+    moderation::moderate(Action::Unban, args.to_owned(), String::new(), session)
+}
+
+fn mute(args: &str, _channel: &str, session: &mut Session) -> Response {
+    moderation::moderate(Action::Mute, args.to_owned(), String::new(), session)
+}
+
+fn unmute(args: &str, _channel: &str, session: &mut Session) -> Response {
+    moderation::moderate(Action::Unmute, args.to_owned(), String::new(), session)
+}
+
+//
+// Test Cases
+#[test]
+fn test_topic_of_direct_messages() {
+    use crate::request::{handle_request, Request};
+    use crate::server::ServerState;
+    use std::sync::Arc;
+
+    let mut session = Session::new(Arc::new(ServerState::new()));
+    session.username = Some("topic_test_carol".to_owned());
+    let channel = "@topic_test_alice+topic_test_bob".to_owned();
+
+    // Outsiders can neither read nor set the topic of a direct message
+    for args in ["", "secret plans"] {
+        let request = Request::RunCommand(channel.clone(), "topic".to_owned(), args.to_owned());
+        assert!(matches!(
+            handle_request(request, &mut session),
+            Response::Error(_)
+        ));
+    }
+
+    session.username = Some("topic_test_alice".to_owned());
+    let request = Request::RunCommand(channel, "topic".to_owned(), String::new());
+    assert!(matches!(
+        handle_request(request, &mut session),
+        Response::Text(_)
+    ));
+}
@@ -0,0 +1,240 @@
+use clap::Parser;
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::message::{Message, DEFAULT_CHANNEL};
+use crate::theme::Theme;
+use crate::timestamp::TimeFormat;
+use crate::Args;
+
+// How many servers we remember
+const MAX_SERVERS: usize = 10;
+
+/// Settings are the client's preferences. eframe saves them between runs,
+/// along with the window's size and position.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub servers: Vec<String>, // "address:port", the last one used first
+    pub username: String,
+    pub theme: Theme,
+    pub palette: String, // The palette file of the custom theme
+    pub font_size: f32,
+    pub time_format: TimeFormat,
+    pub notifications: Notifications,
+    pub last_channel: String,
+    pub tokens: BTreeMap<String, String>, // Login tokens, by "address:port/username"
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            servers: vec![],
+            username: String::new(),
+            theme: Theme::default(),
+            palette: String::new(),
+            font_size: 14.0,
+            time_format: TimeFormat::default(),
+            notifications: Notifications::default(),
+            last_channel: DEFAULT_CHANNEL.to_owned(),
+            tokens: BTreeMap::new(),
+        }
+    }
+}
+
+/// Notifications are what we show desktop notifications for,
+/// while the window is in the background.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Notifications {
+    pub messages: bool, // Every new message
+    pub mentions: bool, // Messages that mention us, even in muted channels
+    pub sound: bool,
+    pub muted: Vec<String>, // Channels we are not told about, as "address:port/channel"
+
+    // Nothing is shown from the start hour until the end hour, local time
+    pub do_not_disturb: bool,
+    pub quiet_start: u32,
+    pub quiet_end: u32,
+}
+
+impl Default for Notifications {
+    fn default() -> Self {
+        Notifications {
+            messages: true,
+            mentions: true,
+            sound: false,
+            muted: vec![],
+            do_not_disturb: false,
+            quiet_start: 22,
+            quiet_end: 7,
+        }
+    }
+}
+
+impl Notifications {
+    /// Whether to notify `username` on `server` about a message that arrived
+    /// at the local `hour`.
+    pub fn wanted(&self, server: &str, message: &Message, username: &str, hour: u32) -> bool {
+        if message.username == username || self.is_quiet(hour) {
+            return false;
+        }
+
+        if message.mentions(username) {
+            return self.mentions;
+        }
+
+        self.messages && !self.is_muted(server, &message.channel)
+    }
+
+    /// Whether the hour falls in the do not disturb hours, which may wrap past midnight.
+    pub fn is_quiet(&self, hour: u32) -> bool {
+        if !self.do_not_disturb {
+            return false;
+        }
+
+        match self.quiet_start <= self.quiet_end {
+            true => (self.quiet_start..self.quiet_end).contains(&hour),
+            false => hour >= self.quiet_start || hour < self.quiet_end,
+        }
+    }
+
+    /// Channels are muted on one server, others may have one of the same name.
+    pub fn is_muted(&self, server: &str, channel: &str) -> bool {
+        self.muted.contains(&server_key(server, channel))
+    }
+
+    /// Mutes the channel, or unmutes it if it was muted.
+    pub fn toggle_mute(&mut self, server: &str, channel: &str) {
+        let key = server_key(server, channel);
+        match self.is_muted(server, channel) {
+            true => self.muted.retain(|c| *c != key),
+            false => self.muted.push(key),
+        }
+    }
+}
+
+fn server_key(server: &str, name: &str) -> String {
+    format!("{}/{}", server, name)
+}
+
+impl Settings {
+    /// The token to log in to `server` as `username`, empty if we have none.
+    pub fn token(&self, server: &str, username: &str) -> &str {
+        let key = server_key(server, username);
+        self.tokens.get(&key).map_or("", |token| token)
+    }
+
+    pub fn set_token(&mut self, server: &str, username: &str, token: String) {
+        self.tokens.insert(server_key(server, username), token);
+    }
+
+    /// Flags given on the command line win over the saved settings.
+    /// The default server is only used if none was saved.
+    pub fn merge_args(&mut self, args: &Args) {
+        let defaults = Args::parse_from(["chatter"]);
+
+        if args.address != defaults.address || args.port != defaults.port || self.servers.is_empty()
+        {
+            self.add_server(format!("{}:{}", args.address, args.port));
+        }
+
+        if args.username != defaults.username {
+            self.username = args.username.clone();
+        }
+
+        if args.time_format != defaults.time_format {
+            self.time_format = args.time_format;
+        }
+    }
+
+    /// Moves the server to the front of the list.
+    pub fn add_server(&mut self, server: String) {
+        self.servers.retain(|s| *s != server);
+        self.servers.insert(0, server);
+        self.servers.truncate(MAX_SERVERS);
+    }
+
+    /// The server we connect to, the last one used.
+    pub fn server(&self) -> Option<&str> {
+        self.servers.first().map(String::as_str)
+    }
+
+    /// Applies the theme and font size to the window. If the palette
+    /// of a custom theme cannot be loaded the dark theme is used.
+    pub fn apply(&self, ctx: &egui::Context) -> Result<(), String> {
+        let visuals = self.theme.visuals(Path::new(&self.palette));
+
+        let mut style = egui::Style {
+            visuals: visuals.clone().unwrap_or_else(|_| egui::Visuals::dark()),
+            ..Default::default()
+        };
+
+        // Every text style grows with the body text
+        let scale = self.font_size / Settings::default().font_size;
+        for font in style.text_styles.values_mut() {
+            font.size *= scale;
+        }
+
+        ctx.set_style(style);
+        visuals.map(|_| ())
+    }
+}
+
+//
+// Test Cases
+#[test]
+fn test_add_server() {
+    let mut settings = Settings::default();
+    settings.add_server("a:1".to_owned());
+    settings.add_server("b:2".to_owned());
+    settings.add_server("a:1".to_owned());
+
+    assert_eq!(settings.servers, vec!["a:1", "b:2"]);
+    assert_eq!(settings.server(), Some("a:1"));
+}
+
+#[test]
+fn test_merge_args() {
+    let mut settings = Settings {
+        servers: vec!["chat.example.com:23432".to_owned()],
+        username: "alice".to_owned(),
+        ..Default::default()
+    };
+
+    // Defaults keep what was saved
+    settings.merge_args(&Args::parse_from(["chatter"]));
+    assert_eq!(settings.server(), Some("chat.example.com:23432"));
+    assert_eq!(settings.username, "alice");
+
+    // Flags win
+    settings.merge_args(&Args::parse_from(["chatter", "-u", "bob", "-p", "4000"]));
+    assert_eq!(settings.server(), Some("127.0.0.1:4000"));
+    assert_eq!(settings.username, "bob");
+    assert_eq!(settings.servers.len(), 2);
+}
+
+#[test]
+fn test_notifications() {
+    let mut notifications = Notifications::default();
+    let mut message = Message::new("bob".to_owned(), "hi @alice".to_owned());
+    let server = "chat.example.com:23432";
+
+    assert!(notifications.wanted(server, &message, "alice", 12));
+    assert!(!notifications.wanted(server, &message, "bob", 12)); // Our own
+
+    // Muted channels only tell us about mentions, on that server only
+    notifications.toggle_mute(server, DEFAULT_CHANNEL);
+    assert!(!notifications.wanted(server, &message, "alice", 12));
+    assert!(notifications.wanted("127.0.0.1:23432", &message, "alice", 12));
+    message.mentions = vec!["alice".to_owned()];
+    assert!(notifications.wanted(server, &message, "alice", 12));
+
+    // Quiet from 22 to 7
+    notifications.do_not_disturb = true;
+    assert!(!notifications.wanted(server, &message, "alice", 23));
+    assert!(!notifications.wanted(server, &message, "alice", 3));
+    assert!(notifications.wanted(server, &message, "alice", 7));
+}
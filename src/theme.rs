@@ -0,0 +1,172 @@
+use eframe::egui;
+use egui::{Color32, Stroke, Visuals};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Theme is the look of the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Theme {
+    #[default]
+    Dark,
+    Light,
+    HighContrast,
+    Custom, // Colors from a palette file
+}
+
+impl Theme {
+    /// The colors of the theme. Custom themes read their palette from the file.
+    pub fn visuals(&self, palette: &Path) -> Result<Visuals, String> {
+        match self {
+            Theme::Dark => Ok(Visuals::dark()),
+            Theme::Light => Ok(Visuals::light()),
+            Theme::HighContrast => Ok(high_contrast()),
+            Theme::Custom => Ok(Palette::load(palette)?.visuals()),
+        }
+    }
+}
+
+/// Palette is a theme of our own, read from a TOML file like
+///
+/// ```toml
+/// dark = true
+/// background = "#1e1e2e"
+/// text = "#cdd6f4"
+/// accent = "#89b4fa"
+/// ```
+///
+/// Colors that are left out come from the light or dark theme.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Palette {
+    pub dark: bool,
+    pub background: Option<String>, // Panels and windows
+    pub text: Option<String>,
+    pub accent: Option<String>, // Selections and links
+    pub code: Option<String>,   // Behind code blocks
+    pub warn: Option<String>,
+    pub error: Option<String>,
+}
+
+impl Palette {
+    pub fn load(path: &Path) -> Result<Palette, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let palette: Palette =
+            toml::from_str(&text).map_err(|e| format!("Bad palette {}: {}", path.display(), e))?;
+
+        // Check the colors now, rather than ignoring them later
+        palette.colors()?;
+        Ok(palette)
+    }
+
+    // The colors that were given, in the order of the fields
+    fn colors(&self) -> Result<[Option<Color32>; 6], String> {
+        let color = |value: &Option<String>| value.as_deref().map(parse_color).transpose();
+
+        Ok([
+            color(&self.background)?,
+            color(&self.text)?,
+            color(&self.accent)?,
+            color(&self.code)?,
+            color(&self.warn)?,
+            color(&self.error)?,
+        ])
+    }
+
+    pub fn visuals(&self) -> Visuals {
+        let mut visuals = match self.dark {
+            true => Visuals::dark(),
+            false => Visuals::light(),
+        };
+        let [background, text, accent, code, warn, error] = self.colors().unwrap_or_default();
+
+        if let Some(background) = background {
+            visuals.panel_fill = background;
+            visuals.window_fill = background;
+        }
+        if let Some(text) = text {
+            visuals.override_text_color = Some(text);
+        }
+        if let Some(accent) = accent {
+            visuals.selection.bg_fill = accent;
+            visuals.hyperlink_color = accent;
+        }
+        visuals.code_bg_color = code.unwrap_or(visuals.code_bg_color);
+        visuals.warn_fg_color = warn.unwrap_or(visuals.warn_fg_color);
+        visuals.error_fg_color = error.unwrap_or(visuals.error_fg_color);
+
+        visuals
+    }
+}
+
+/// Parses a color written as #rrggbb.
+pub fn parse_color(text: &str) -> Result<Color32, String> {
+    let error = || format!("Colors are written as #rrggbb, not {}", text);
+
+    let hex = text.trim().strip_prefix('#').ok_or_else(error)?;
+    if hex.len() != 6 {
+        return Err(error());
+    }
+
+    let channel = |i: usize| {
+        hex.get(i..i + 2)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+            .ok_or_else(error)
+    };
+    Ok(Color32::from_rgb(channel(0)?, channel(2)?, channel(4)?))
+}
+
+// White on black, with strong outlines
+fn high_contrast() -> Visuals {
+    let mut visuals = Visuals::dark();
+
+    visuals.override_text_color = Some(Color32::WHITE);
+    visuals.panel_fill = Color32::BLACK;
+    visuals.window_fill = Color32::BLACK;
+    visuals.extreme_bg_color = Color32::BLACK;
+    visuals.faint_bg_color = Color32::from_gray(45);
+    visuals.code_bg_color = Color32::from_gray(35);
+    visuals.hyperlink_color = Color32::from_rgb(0, 230, 255);
+    visuals.warn_fg_color = Color32::from_rgb(255, 220, 0);
+    visuals.error_fg_color = Color32::from_rgb(255, 90, 90);
+    visuals.selection.bg_fill = Color32::from_rgb(0, 90, 200);
+    visuals.selection.stroke = Stroke::new(1.0, Color32::WHITE);
+
+    let widgets = &mut visuals.widgets;
+    for widget in [
+        &mut widgets.noninteractive,
+        &mut widgets.inactive,
+        &mut widgets.hovered,
+        &mut widgets.active,
+        &mut widgets.open,
+    ] {
+        widget.fg_stroke = Stroke::new(1.5, Color32::WHITE);
+        widget.bg_stroke = Stroke::new(1.0, Color32::WHITE);
+    }
+
+    visuals
+}
+
+//
+// Test Cases
+#[test]
+fn test_parse_color() {
+    assert_eq!(parse_color("#ff8000"), Ok(Color32::from_rgb(255, 128, 0)));
+    assert_eq!(parse_color(" #00FF00 "), Ok(Color32::from_rgb(0, 255, 0)));
+    assert!(parse_color("ff8000").is_err());
+    assert!(parse_color("#ff80").is_err());
+    assert!(parse_color("#gg0000").is_err());
+}
+
+#[test]
+fn test_palette() {
+    let palette: Palette = toml::from_str("dark = true\nbackground = \"#101010\"").unwrap();
+    let visuals = palette.visuals();
+
+    assert!(visuals.dark_mode);
+    assert_eq!(visuals.panel_fill, Color32::from_gray(16));
+    assert_eq!(visuals.hyperlink_color, Visuals::dark().hyperlink_color);
+
+    let palette: Palette = toml::from_str("text = \"white\"").unwrap();
+    assert!(palette.colors().is_err());
+}
@@ -0,0 +1,225 @@
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate, Offset, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+/// TimeFormat is how the time of a message is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+pub enum TimeFormat {
+    #[default]
+    #[value(name = "24h")]
+    H24, // 14:05
+    #[value(name = "12h")]
+    H12, // 2:05 PM
+    Relative, // 5 min ago
+}
+
+/// Zone is the timezone times are shown in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Zone {
+    #[default]
+    Local,
+    Named(chrono_tz::Tz),
+    Fixed(FixedOffset), // e.g. +02:00, without daylight saving
+}
+
+impl Zone {
+    /// Parses a timezone name like "Europe/Berlin" or an offset like "+02:00".
+    /// Empty means local time.
+    pub fn parse(name: &str) -> Option<Zone> {
+        match name.trim() {
+            "" | "local" => Some(Zone::Local),
+            name if name.starts_with(['+', '-']) => parse_offset(name).map(Zone::Fixed),
+            name => name.parse().ok().map(Zone::Named),
+        }
+    }
+}
+
+// An offset from UTC written as ±HH:MM
+fn parse_offset(text: &str) -> Option<FixedOffset> {
+    let (sign, rest) = text.split_at(1);
+    let (hours, minutes) = rest.split_once(':')?;
+    if hours.len() != 2 || minutes.len() != 2 {
+        return None;
+    }
+
+    let hours: i32 = hours.parse().ok().filter(|h| (0..24).contains(h))?;
+    let minutes: i32 = minutes.parse().ok().filter(|m| (0..60).contains(m))?;
+    let seconds = (hours * 60 + minutes) * 60;
+
+    match sign {
+        "+" => FixedOffset::east_opt(seconds),
+        _ => FixedOffset::west_opt(seconds),
+    }
+}
+
+/// Clock turns message timestamps into text.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Clock {
+    pub format: TimeFormat,
+    pub zone: Zone,
+}
+
+impl Clock {
+    fn date_time(&self, timestamp_ms: i64) -> DateTime<FixedOffset> {
+        let utc = Utc
+            .timestamp_millis_opt(timestamp_ms)
+            .single()
+            .unwrap_or_default();
+
+        match self.zone {
+            Zone::Local => with_offset(utc.with_timezone(&Local)),
+            Zone::Named(tz) => with_offset(utc.with_timezone(&tz)),
+            Zone::Fixed(offset) => utc.with_timezone(&offset),
+        }
+    }
+
+    /// The short time shown next to a message.
+    pub fn time(&self, timestamp_ms: i64, now_ms: i64) -> String {
+        let time = self.date_time(timestamp_ms);
+
+        match self.format {
+            TimeFormat::H24 => time.format("%H:%M").to_string(),
+            TimeFormat::H12 => time.format("%-I:%M %p").to_string(),
+            TimeFormat::Relative => {
+                let age = Duration::milliseconds(now_ms - timestamp_ms);
+
+                if age < Duration::minutes(1) {
+                    "just now".to_owned()
+                } else if age < Duration::hours(1) {
+                    format!("{} min ago", age.num_minutes())
+                } else if age < Duration::days(1) {
+                    format!("{} h ago", age.num_hours())
+                } else {
+                    time.format("%-d %b %H:%M").to_string()
+                }
+            }
+        }
+    }
+
+    /// The whole date and time, e.g. for a tooltip.
+    pub fn full(&self, timestamp_ms: i64) -> String {
+        let time = self.date_time(timestamp_ms);
+
+        match self.format {
+            TimeFormat::H12 => time.format("%A, %-d %B %Y %-I:%M:%S %p (UTC%:z)"),
+            _ => time.format("%A, %-d %B %Y %H:%M:%S (UTC%:z)"),
+        }
+        .to_string()
+    }
+
+    /// The day the message was sent on, in our timezone.
+    pub fn day(&self, timestamp_ms: i64) -> NaiveDate {
+        self.date_time(timestamp_ms).date_naive()
+    }
+
+    /// Names a day for the separators in the message list.
+    pub fn day_label(&self, timestamp_ms: i64, now_ms: i64) -> String {
+        let day = self.day(timestamp_ms);
+        let today = self.day(now_ms);
+
+        if day == today {
+            "Today".to_owned()
+        } else if today.pred_opt() == Some(day) {
+            "Yesterday".to_owned()
+        } else {
+            day.format("%A, %-d %B %Y").to_string()
+        }
+    }
+}
+
+/// A length of time in words, like "3d 4h 5m". Seconds are only shown below a minute.
+pub fn describe_duration(secs: u64) -> String {
+    let (days, hours, minutes) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
+
+    match (days, hours) {
+        _ if secs < 60 => format!("{}s", secs),
+        (0, 0) => format!("{}m", minutes),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h {}m", days, hours, minutes),
+    }
+}
+
+fn with_offset<Tz: TimeZone>(time: DateTime<Tz>) -> DateTime<FixedOffset> {
+    let offset = time.offset().fix();
+    time.with_timezone(&offset)
+}
+
+//
+// Test Cases
+#[test]
+fn test_clock_formats() {
+    // 2023-04-03 13:05:09 UTC
+    let timestamp_ms = 1_680_527_109_000;
+    let berlin = Zone::parse("Europe/Berlin").unwrap();
+
+    let clock = Clock {
+        format: TimeFormat::H24,
+        zone: berlin,
+    };
+    assert_eq!(clock.time(timestamp_ms, timestamp_ms), "15:05");
+    assert_eq!(
+        clock.full(timestamp_ms),
+        "Monday, 3 April 2023 15:05:09 (UTC+02:00)"
+    );
+
+    let clock = Clock {
+        format: TimeFormat::H12,
+        zone: berlin,
+    };
+    assert_eq!(clock.time(timestamp_ms, timestamp_ms), "3:05 PM");
+
+    let clock = Clock {
+        format: TimeFormat::Relative,
+        zone: berlin,
+    };
+    let minute = 60 * 1000;
+    assert_eq!(clock.time(timestamp_ms, timestamp_ms + 10), "just now");
+    assert_eq!(
+        clock.time(timestamp_ms, timestamp_ms + 5 * minute),
+        "5 min ago"
+    );
+    assert_eq!(
+        clock.time(timestamp_ms, timestamp_ms + 180 * minute),
+        "3 h ago"
+    );
+
+    assert!(Zone::parse("Not/AZone").is_none());
+
+    // Offsets
+    let clock = Clock {
+        format: TimeFormat::H24,
+        zone: Zone::parse("-03:30").unwrap(),
+    };
+    assert_eq!(clock.time(timestamp_ms, timestamp_ms), "09:35");
+    assert_eq!(
+        Zone::parse("+02:00"),
+        Some(Zone::Fixed(FixedOffset::east_opt(7200).unwrap()))
+    );
+    for bad in ["+2:00", "+-1:00", "+24:00", "+02:60", "+0200", "-ab:cd"] {
+        assert!(Zone::parse(bad).is_none(), "{}", bad);
+    }
+}
+
+#[test]
+fn test_day_label() {
+    let clock = Clock {
+        format: TimeFormat::H24,
+        zone: Zone::parse("UTC").unwrap(),
+    };
+    let day = 24 * 60 * 60 * 1000;
+    let now = 1_680_527_109_000; // Monday
+
+    assert_eq!(clock.day_label(now, now), "Today");
+    assert_eq!(clock.day_label(now - day, now), "Yesterday");
+    assert_eq!(
+        clock.day_label(now - 2 * day, now),
+        "Saturday, 1 April 2023"
+    );
+}
+
+#[test]
+fn test_describe_duration() {
+    assert_eq!(describe_duration(42), "42s");
+    assert_eq!(describe_duration(5 * 60 + 3), "5m");
+    assert_eq!(describe_duration(2 * 3600 + 60), "2h 1m");
+    assert_eq!(describe_duration(3 * 86400 + 4 * 3600 + 5 * 60), "3d 4h 5m");
+}
@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long someone counts as typing after their last notification.
+/// Clients repeat the notification while the user keeps typing.
+pub const EXPIRY: Duration = Duration::from_secs(5);
+
+/// How often a client repeats the notification, well within the expiry.
+pub const REPEAT: Duration = Duration::from_secs(3);
+
+/// How long after their last edit a client stops saying the user is typing,
+/// so a draft left in the message box does not count.
+pub const IDLE: Duration = Duration::from_secs(5);
+
+/// Typing tracks who is composing a message in which channel.
+/// It only lives in memory, nobody cares after a restart.
+#[derive(Debug, Default)]
+pub struct Typing {
+    channels: Mutex<BTreeMap<String, BTreeMap<String, Instant>>>, // Channel, username, last notified
+}
+
+impl Typing {
+    pub fn start(&self, channel: &str, username: &str, now: Instant) {
+        let mut channels = self.channels.lock().unwrap();
+        channels
+            .entry(channel.to_owned())
+            .or_default()
+            .insert(username.to_owned(), now);
+    }
+
+    pub fn stop(&self, channel: &str, username: &str) {
+        let mut channels = self.channels.lock().unwrap();
+        if let Some(users) = channels.get_mut(channel) {
+            users.remove(username);
+        }
+    }
+
+    /// Forgets the user in every channel, e.g. when they leave.
+    pub fn remove_user(&self, username: &str) {
+        let mut channels = self.channels.lock().unwrap();
+        for users in channels.values_mut() {
+            users.remove(username);
+        }
+    }
+
+    /// Who is typing in the channel, apart from `username`.
+    pub fn who(&self, channel: &str, username: &str, now: Instant) -> Vec<String> {
+        let mut channels = self.channels.lock().unwrap();
+        let Some(users) = channels.get_mut(channel) else {
+            return vec![];
+        };
+
+        users.retain(|_, last| now.duration_since(*last) < EXPIRY);
+
+        users
+            .keys()
+            .filter(|name| name.as_str() != username)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Describes who is typing, e.g. "alice and bob are typing…".
+pub fn describe(users: &[String]) -> Option<String> {
+    match users {
+        [] => None,
+        [one] => Some(format!("{} is typing…", one)),
+        [one, two] => Some(format!("{} and {} are typing…", one, two)),
+        _ => Some("Several people are typing…".to_owned()),
+    }
+}
+
+//
+// Test Cases
+#[test]
+fn test_typing_expires() {
+    let typing = Typing::default();
+    let now = Instant::now();
+
+    typing.start("general", "alice", now);
+    typing.start("general", "bob", now + Duration::from_secs(3));
+    typing.start("random", "carol", now);
+
+    assert_eq!(typing.who("general", "bob", now), vec!["alice"]);
+    assert_eq!(
+        typing.who("general", "", now + EXPIRY),
+        vec!["bob".to_string()]
+    );
+
+    typing.stop("general", "bob");
+    assert!(typing.who("general", "", now).is_empty());
+
+    typing.remove_user("carol");
+    assert!(typing.who("random", "", now).is_empty());
+}
+
+#[test]
+fn test_describe() {
+    let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+    assert_eq!(describe(&[]), None);
+    assert_eq!(describe(&names(&["alice"])).unwrap(), "alice is typing…");
+    assert_eq!(
+        describe(&names(&["alice", "bob"])).unwrap(),
+        "alice and bob are typing…"
+    );
+    assert_eq!(
+        describe(&names(&["a", "b", "c"])).unwrap(),
+        "Several people are typing…"
+    );
+}